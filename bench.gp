@@ -8,16 +8,30 @@ set output "gp_out.png"
 
 sources = system("ls *.csv")
 
-set multiplot layout 3,2 columns
+# A column of medians per allocator, titled by its header.
+set key autotitle columnhead
+set logscale x
+set multiplot layout 5,2 columns
 do for [source in sources] {
     set title source
-    plot source using 1:2 title "first" with lines linewidth 3, \
-         source using 1:3 title "best" with lines linewidth 3, \
-         source using 1:4 title "bump" with lines linewidth 3, \
-         source using 1:5 title "debump" with lines linewidth 3, \
-         source using 1:6 title "pool" with lines linewidth 3, \
-         source using 1:7 title "statiq" with lines linewidth 3, \
-         source using 1:8 title "system" with lines linewidth 3 
+    plot for [i=2:*] source using 1:i with lines linewidth 3
+}
+
+unset multiplot
+unset logscale x
+
+# Heap maps: a box per region, free (0), used (1) or metadata (2).
+set output "gp_heapmap.png"
+maps = system("ls *.map")
+set xlabel "heap offset (bytes)"
+unset ylabel
+set yrange [0:1]
+unset ytics
+set style fill solid noborder
+set multiplot layout words(maps),1
+do for [map in maps] {
+    set title map
+    plot map using ($1+$2/2):(1):2:($3+1) with boxes linecolor variable notitle
 }
 
 unset multiplot
@@ -1,57 +1,169 @@
 #![feature(allocator_api)]
-#![feature(type_name_of_val)]
 
 use std::{
     alloc::Layout,
     collections::hash_map::DefaultHasher,
-    fs::{self, File},
+    fmt::Write as _,
+    fs,
     hash::{Hash, Hasher},
     hint::black_box,
-    io::{BufWriter, Write},
+    ops::Range,
+    sync::mpsc,
+    thread,
     time::{self, Duration, Instant, SystemTime},
 };
 
-use alloq::{bump, debump, list::best, list::first, pool, statiq, system, Alloqator};
+use alloq::{
+    bump, debump, fragmentation::HeapWalk, list::best, list::first, pool, statiq, system, Alloqator,
+};
 
-pub const HEAP_SIM_SIZE: usize = 1024 * 1024 * 512;
-pub static mut HEAP_SIM: [u8; HEAP_SIM_SIZE] = [0u8; HEAP_SIM_SIZE];
-pub const TEST_COUNT: usize = 10_usize.pow(3);
+/// Each allocator gets its own heap of this size, and a new allocator over it for every run.
+pub const HEAP_SIZE: usize = 1024 * 1024 * 64;
+/// Runs measured for each workload and count.
+pub const SAMPLES: usize = 11;
+/// Runs before them, not measured, to warm the caches and fault the heap's pages in.
+pub const WARMUP: usize = 2;
+/// The `n` given to each workload, usually the number of allocations.
+pub const COUNTS: [usize; 7] = [1, 10, 100, 500, 1000, 2000, 5000];
+pub const THREAD_COUNT: usize = 4;
 
-fn get_time(f: impl FnOnce()) -> Duration {
-    let start = Instant::now();
-    f();
-    Instant::now() - start
-}
-
-macro_rules! unit_bench {
-    ($dir:expr, $name:expr, $($alloq:expr),*) => {{
-        let file = File::create(format!("{}/{}.csv", $dir, stringify!($name))).unwrap();
-        let mut w = BufWriter::new(file);
-        writeln!(w, "{}", stringify!($name)).unwrap();
-        write!(w, "count, ").unwrap();
-        $(write!(w, "{}, ", std::any::type_name_of_val($alloq)).unwrap();)*
-        writeln!(w, "").unwrap();
-        println!("benchmarking {}", stringify!($name));
-        for n in (0..=TEST_COUNT) {
-            write!(w, "{n}, ").unwrap();
-            $(
-                let t = $name($alloq, n);
-                write!(w, "{}, ", t.as_nanos()).unwrap();
-            )*
-            writeln!(w, "").unwrap();
+type Workload<A> = (&'static str, fn(&A, usize) -> Duration);
+
+fn workloads<A: Alloqator + Sync>() -> [Workload<A>; 10] {
+    [
+        ("linear_allocation", linear_allocation),
+        ("linear_deallocation", linear_deallocation),
+        ("reverse_deallocation", reverse_deallocation),
+        ("vector_pushing", vector_pushing),
+        ("vector_fragmentation", vector_fragmentation),
+        ("reset", reset),
+        ("contended_allocation", contended_allocation),
+        ("random_sizes", random_sizes),
+        ("producer_consumer", producer_consumer),
+        ("mixed_lifetimes", mixed_lifetimes),
+    ]
+}
+
+/// Nanoseconds of the measured runs of a workload.
+#[derive(Clone, Copy, Debug)]
+struct Stats {
+    min: u128,
+    p10: u128,
+    median: u128,
+    p90: u128,
+    max: u128,
+    mean: u128,
+}
+
+impl Stats {
+    fn new(samples: &mut [Duration]) -> Self {
+        samples.sort_unstable();
+        let at = |percentile: usize| samples[(samples.len() - 1) * percentile / 100].as_nanos();
+        Self {
+            min: at(0),
+            p10: at(10),
+            median: at(50),
+            p90: at(90),
+            max: at(100),
+            mean: samples.iter().sum::<Duration>().as_nanos() / samples.len() as u128,
+        }
+    }
+}
+
+struct Record {
+    workload: &'static str,
+    alloq: &'static str,
+    count: usize,
+    stats: Stats,
+}
+
+/// Runs every workload on allocators built by `make`, each time on the same heap.
+fn bench<A: Alloqator + Sync>(
+    alloq: &'static str,
+    make: impl Fn(Range<*mut u8>) -> A,
+    records: &mut Vec<Record>,
+) {
+    println!("benchmarking {alloq}");
+    let mut heap = vec![0u8; HEAP_SIZE];
+    for (workload, run) in workloads::<A>() {
+        for count in COUNTS {
+            let mut samples = Vec::with_capacity(SAMPLES);
+            for i in 0..WARMUP + SAMPLES {
+                let a = make(heap.as_mut_ptr_range());
+                let time = run(&a, count);
+                if i >= WARMUP {
+                    samples.push(time);
+                }
+            }
+            records.push(Record {
+                workload,
+                alloq,
+                count,
+                stats: Stats::new(&mut samples),
+            });
         }
-    }};
+    }
 }
 
-macro_rules! run_benches {
-    ($dir:expr, $($alloq:expr),*) => {
-        unit_bench!($dir, linear_allocation, $($alloq),*);
-        unit_bench!($dir, linear_deallocation, $($alloq),*);
-        unit_bench!($dir, reverse_deallocation, $($alloq),*);
-        unit_bench!($dir, vector_pushing, $($alloq),*);
-        unit_bench!($dir, vector_fragmentation, $($alloq),*);
-        unit_bench!($dir, reset, $($alloq),*);
-    };
+/// A `<workload>.csv` per workload, with the medians of each allocator in a column named after
+/// it, which `bench.gp` uses as the title.
+fn write_csv(dir: &str, records: &[Record]) {
+    let workloads = first_seen(records.iter().map(|r| r.workload));
+    let alloqs = first_seen(records.iter().map(|r| r.alloq));
+    for workload in workloads {
+        let mut csv = format!("count,{}\n", alloqs.join(","));
+        for count in COUNTS {
+            let medians: Vec<_> = alloqs
+                .iter()
+                .map(|&alloq| {
+                    let record = records
+                        .iter()
+                        .find(|r| (r.workload, r.alloq, r.count) == (workload, alloq, count))
+                        .unwrap();
+                    record.stats.median.to_string()
+                })
+                .collect();
+            writeln!(csv, "{count},{}", medians.join(",")).unwrap();
+        }
+        fs::write(format!("{dir}/{workload}.csv"), csv).unwrap();
+    }
+}
+
+/// The distinct items, in the order they first appear.
+fn first_seen<T: PartialEq>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let mut seen = Vec::new();
+    for item in items {
+        if !seen.contains(&item) {
+            seen.push(item);
+        }
+    }
+    seen
+}
+
+/// Every record, with all its statistics, in `results.json`.
+fn write_json(dir: &str, records: &[Record]) {
+    let mut json = format!(
+        "{{\n  \"samples\": {SAMPLES},\n  \"warmup\": {WARMUP},\n  \"heap_size\": {HEAP_SIZE},\n  \"results\": [\n"
+    );
+    for (i, r) in records.iter().enumerate() {
+        let Stats {
+            min,
+            p10,
+            median,
+            p90,
+            max,
+            mean,
+        } = r.stats;
+        write!(
+            json,
+            "    {{\"workload\": \"{}\", \"alloq\": \"{}\", \"count\": {}, \"min_ns\": {min}, \"p10_ns\": {p10}, \"median_ns\": {median}, \"p90_ns\": {p90}, \"max_ns\": {max}, \"mean_ns\": {mean}}}",
+            r.workload, r.alloq, r.count
+        )
+        .unwrap();
+        json.push_str(if i + 1 < records.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("  ]\n}\n");
+    fs::write(format!("{dir}/results.json"), json).unwrap();
 }
 
 fn main() {
@@ -63,27 +175,50 @@ fn main() {
     let dir = format!("alloq-bench-{}", hasher.finish());
     fs::create_dir(&dir).expect("can't create a directory");
 
-    println!("preparing alloqators");
-
-    let bump = bump::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let debump = debump::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let pool = unsafe {
-        pool::Alloq::with_chunk_size(HEAP_SIM.as_mut_ptr_range(), HEAP_SIM_SIZE / 1024, 2)
-    };
-    let flex_pool = pool::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let first = first::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let best = best::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let statiq = statiq::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-    let system = system::Alloq::new(unsafe { HEAP_SIM.as_mut_ptr_range() });
-
-    println!("running benchmarks");
-    println!("heap range: {:?}", unsafe { HEAP_SIM.as_mut_ptr_range() });
-    run_benches!(dir, &first, &best, &bump, &debump, &pool, &statiq, &system, &flex_pool);
+    println!("running benchmarks ({WARMUP} warmup and {SAMPLES} measured runs each)");
+    let mut records = Vec::new();
+    bench("first", first::Alloq::new, &mut records);
+    bench("best", best::Alloq::new, &mut records);
+    bench("bump", <bump::Alloq>::new, &mut records);
+    bench("atomic_bump", bump::atomic::Alloq::new, &mut records);
+    bench("debump", <debump::Alloq>::new, &mut records);
+    bench(
+        "pool",
+        |range| unsafe { <pool::Alloq>::with_chunk_size(range, HEAP_SIZE / 16384, 2) },
+        &mut records,
+    );
+    bench("flex_pool", <pool::Alloq>::new, &mut records);
+    bench("statiq", <statiq::Alloq>::new, &mut records);
+    bench("system", system::Alloq::new, &mut records);
+    write_csv(&dir, &records);
+    write_json(&dir, &records);
+
+    println!("measuring fragmentation");
+    let mut heap = vec![0u8; HEAP_SIZE];
+    heap_map(&dir, "first", &first::Alloq::new(heap.as_mut_ptr_range()));
+    heap_map(&dir, "best", &best::Alloq::new(heap.as_mut_ptr_range()));
+    heap_map(&dir, "pool", &<pool::Alloq>::new(heap.as_mut_ptr_range()));
     println!("benchmarks results saved on {dir}");
 }
 
+fn get_time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+/// A xorshift generator, so every allocator gets the same sequence.
+fn random(seed: u64) -> impl FnMut() -> usize {
+    let mut state = seed;
+    move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    }
+}
+
 fn linear_allocation<A: Alloqator>(a: &A, n: usize) -> Duration {
-    unsafe { a.reset() };
     let layout = Layout::from_size_align(32, 2).unwrap();
     let mut v = Vec::with_capacity(n);
     let t = get_time(|| {
@@ -96,33 +231,33 @@ fn linear_allocation<A: Alloqator>(a: &A, n: usize) -> Duration {
         "linear_allocation assert error: {}:{n} can't allocate memory",
         std::any::type_name::<A>()
     );
+    for ptr in v {
+        unsafe { a.dealloq(ptr, layout) };
+    }
     t
 }
 
 fn linear_deallocation<A: Alloqator>(a: &A, n: usize) -> Duration {
-    unsafe { a.reset() };
     let layout = Layout::from_size_align(32, 2).unwrap();
     let ptrs: Vec<_> = (0..n).map(|_| a.alloq(layout)).collect();
     get_time(|| {
         for ptr in ptrs {
-            unsafe { a.dealloq(ptr.clone(), layout) };
+            unsafe { a.dealloq(ptr, layout) };
         }
     })
 }
 
 fn reverse_deallocation<A: Alloqator>(a: &A, n: usize) -> Duration {
-    unsafe { a.reset() };
     let layout = Layout::from_size_align(32, 2).unwrap();
     let ptrs: Vec<_> = (0..n).map(|_| a.alloq(layout)).collect();
     get_time(|| {
         for ptr in ptrs.iter().rev() {
-            unsafe { a.dealloq(ptr.clone(), layout) };
+            unsafe { a.dealloq(*ptr, layout) };
         }
     })
 }
 
 fn vector_pushing<A: Alloqator>(a: &A, n: usize) -> Duration {
-    unsafe { a.reset() };
     let mut v = Vec::new_in(a);
     let t = get_time(|| {
         for x in 0..n {
@@ -138,13 +273,14 @@ fn vector_pushing<A: Alloqator>(a: &A, n: usize) -> Duration {
     t
 }
 
-fn reset<A: Alloqator>(a: &A, _n: usize) -> Duration {
-    unsafe { a.reset() };
+/// Resets after `n` blocks were allocated and freed, so `list` and `pool` drop them from their
+/// lists.
+fn reset<A: Alloqator>(a: &A, n: usize) -> Duration {
+    linear_deallocation(a, n);
     get_time(|| unsafe { a.reset() })
 }
 
 fn vector_fragmentation<A: Alloqator>(a: &A, n: usize) -> Duration {
-    unsafe { a.reset() };
     let mut v1 = Vec::new_in(a);
     let mut v2 = Vec::new_in(a);
     let mut v3 = Vec::new_in(a);
@@ -176,3 +312,117 @@ fn vector_fragmentation<A: Alloqator>(a: &A, n: usize) -> Duration {
     );
     t
 }
+
+fn contended_allocation<A: Alloqator + Sync>(a: &A, n: usize) -> Duration {
+    let layout = Layout::from_size_align(32, 2).unwrap();
+    get_time(|| {
+        thread::scope(|s| {
+            for _ in 0..THREAD_COUNT {
+                s.spawn(|| {
+                    let ptrs: Vec<_> = (0..n / THREAD_COUNT).map(|_| a.alloq(layout)).collect();
+                    for ptr in ptrs {
+                        unsafe { a.dealloq(ptr, layout) };
+                    }
+                });
+            }
+        });
+    })
+}
+
+/// `n` allocations of 1 to 2048 bytes, each followed by freeing a random live block a third of
+/// the time.
+fn random_sizes<A: Alloqator>(a: &A, n: usize) -> Duration {
+    let mut random = random(0x9e37_79b9_7f4a_7c15);
+    let mut live = Vec::with_capacity(n);
+    let t = get_time(|| {
+        for _ in 0..n {
+            let layout = Layout::from_size_align(1 + random() % 2048, 8 << (random() % 2)).unwrap();
+            live.push((a.alloq(layout), layout));
+            if random().is_multiple_of(3) {
+                let (ptr, layout) = live.swap_remove(random() % live.len());
+                unsafe { a.dealloq(ptr, layout) };
+            }
+        }
+    });
+    for (ptr, layout) in live {
+        unsafe { a.dealloq(ptr, layout) };
+    }
+    t
+}
+
+/// Half of the threads allocate `n` blocks in total and send them to the other half, which free
+/// them, so blocks are freed by other threads than the ones that allocated them.
+fn producer_consumer<A: Alloqator + Sync>(a: &A, n: usize) -> Duration {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let pairs = THREAD_COUNT / 2;
+    get_time(|| {
+        thread::scope(|s| {
+            for _ in 0..pairs {
+                // Addresses, as pointers aren't `Send`.
+                let (sender, receiver) = mpsc::sync_channel::<usize>(64);
+                s.spawn(move || {
+                    for _ in 0..n / pairs {
+                        sender.send(a.alloq(layout) as usize).unwrap();
+                    }
+                });
+                s.spawn(move || {
+                    for addr in receiver {
+                        unsafe { a.dealloq(addr as *mut u8, layout) };
+                    }
+                });
+            }
+        });
+    })
+}
+
+/// Long-lived blocks kept until the end, medium-lived ones in a ring of 16 and short-lived ones
+/// freed right away, interleaved like in a server handling requests.
+fn mixed_lifetimes<A: Alloqator>(a: &A, n: usize) -> Duration {
+    let mut random = random(0x2545_f491_4f6c_dd1d);
+    let mut long = Vec::with_capacity(n / 16 + 1);
+    let mut ring = [(std::ptr::null_mut(), Layout::new::<u8>()); 16];
+    let t = get_time(|| {
+        for i in 0..n {
+            let short = Layout::from_size_align(16 + random() % 256, 8).unwrap();
+            let ptr = a.alloq(short);
+            let medium = Layout::from_size_align(64 + random() % 1024, 8).unwrap();
+            let (old, old_layout) = std::mem::replace(&mut ring[i % 16], (a.alloq(medium), medium));
+            unsafe {
+                a.dealloq(old, old_layout);
+                a.dealloq(ptr, short);
+            }
+            if i % 16 == 0 {
+                let layout = Layout::from_size_align(128 + random() % 4096, 16).unwrap();
+                long.push((a.alloq(layout), layout));
+            }
+        }
+    });
+    for (ptr, layout) in ring.into_iter().chain(long) {
+        unsafe { a.dealloq(ptr, layout) };
+    }
+    t
+}
+
+/// Allocates and frees blocks of pseudo-random sizes, keeping about half of them, and writes
+/// the resulting heap map to `{dir}/{name}.map`.
+fn heap_map<A: HeapWalk>(dir: &str, name: &str, a: &A) {
+    let mut random = random(0x2545_f491_4f6c_dd1d);
+    let mut live = Vec::new();
+    for _ in 0..10_000 {
+        if live.is_empty() || !random().is_multiple_of(3) {
+            let layout = Layout::from_size_align(8 + random() % 1024, 8).unwrap();
+            live.push((a.alloq(layout), layout));
+        } else {
+            let (ptr, layout) = live.swap_remove(random() % live.len());
+            unsafe { a.dealloq(ptr, layout) };
+        }
+    }
+    let report = a.fragmentation();
+    println!("{name}: {report}");
+    let mut map = String::new();
+    a.write_heap_map(&mut map).unwrap();
+    fs::write(format!("{dir}/{name}.map"), map).unwrap();
+    for (ptr, layout) in live {
+        unsafe { a.dealloq(ptr, layout) };
+    }
+}
@@ -0,0 +1,235 @@
+#![feature(allocator_api)]
+
+#[path = "replay/resolve.rs"]
+mod resolve;
+
+use std::{
+    alloc::Layout,
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    ops::Range,
+    ptr::{self, NonNull},
+    sync::Mutex,
+    time::{self, Duration, Instant, SystemTime},
+};
+
+use alloq::{
+    bump, debump,
+    list::{best, first},
+    pool, statiq, system,
+    trace::{Event, Trace},
+    Alloqator,
+};
+use resolve::{resolve, Op};
+
+pub const HEAP_SIZE: usize = 1024 * 1024 * 256;
+pub const WORKLOAD_STEPS: usize = 100_000;
+
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+struct Replay {
+    time: Duration,
+    /// Most bytes of live blocks at once, counting each block's usable size, so allocators that
+    /// round blocks up count the slack too.
+    peak: usize,
+    /// Bytes of the heap spanned by blocks, from the lowest to the highest, if they are in the
+    /// heap.
+    span: Option<usize>,
+    failed: usize,
+}
+
+/// Replays `ops` on `a`, built over `heap`.
+fn replay<A: Alloqator>(a: &A, heap: Range<*mut u8>, ops: &[Op], blocks: usize) -> Replay {
+    unsafe { a.reset() };
+    // Each live block, with its layout and usable size.
+    let mut live: Vec<Option<(NonNull<u8>, Layout, usize)>> = vec![None; blocks];
+    let (mut outstanding, mut peak) = (0usize, 0);
+    let (mut lowest, mut highest) = (usize::MAX, 0);
+    let mut outside = false;
+    let mut failed = 0;
+    let mut track = |block: NonNull<[u8]>| {
+        let start = block.cast::<u8>().as_ptr();
+        if heap.contains(&start) {
+            lowest = lowest.min(start as usize);
+            highest = highest.max(start as usize + block.len());
+        } else if !block.is_empty() {
+            outside = true;
+        }
+    };
+    let start = Instant::now();
+    for &op in ops {
+        match op {
+            Op::Alloc(id, layout) => match a.allocate(layout) {
+                Ok(block) => {
+                    track(block);
+                    outstanding += block.len();
+                    peak = peak.max(outstanding);
+                    live[id] = Some((block.cast(), layout, block.len()));
+                }
+                Err(_) => failed += 1,
+            },
+            Op::Free(id) => {
+                if let Some((ptr, layout, len)) = live[id].take() {
+                    unsafe { a.deallocate(ptr, layout) };
+                    outstanding -= len;
+                }
+            }
+            Op::Realloc(id, new_layout) => {
+                let Some((ptr, old_layout, old_len)) = live[id] else {
+                    continue;
+                };
+                let block = unsafe {
+                    if new_layout.size() >= old_layout.size() {
+                        a.grow(ptr, old_layout, new_layout)
+                    } else {
+                        a.shrink(ptr, old_layout, new_layout)
+                    }
+                };
+                match block {
+                    Ok(block) => {
+                        track(block);
+                        outstanding = outstanding - old_len + block.len();
+                        peak = peak.max(outstanding);
+                        live[id] = Some((block.cast(), new_layout, block.len()));
+                    }
+                    // The old block is still there.
+                    Err(_) => failed += 1,
+                }
+            }
+            Op::Reset => {
+                unsafe { a.reset() };
+                live.fill(None);
+                outstanding = 0;
+            }
+        }
+    }
+    let time = start.elapsed();
+    for (ptr, layout, _) in live.into_iter().flatten() {
+        unsafe { a.deallocate(ptr, layout) };
+    }
+    Replay {
+        time,
+        peak,
+        span: (!outside).then_some(highest.saturating_sub(lowest)),
+        failed,
+    }
+}
+
+/// Records a mixed workload on the system allocator: growing vectors, short-lived blocks of
+/// random sizes and a few over-aligned ones.
+fn record_workload() -> Vec<Event> {
+    let start = Instant::now();
+    let a = Trace::with(
+        system::Alloq::new(ptr::null_mut()..ptr::null_mut()),
+        |event| EVENTS.lock().unwrap().push(event),
+    )
+    .clock(|| {
+        // The same instant for the whole process.
+        static START: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    });
+    let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize
+    };
+    let mut vectors: Vec<Vec<u64, &_>> = Vec::new();
+    let mut blocks = Vec::new();
+    for step in 0..WORKLOAD_STEPS {
+        match random() % 8 {
+            0 => vectors.push(Vec::new_in(&a)),
+            1..=2 if !vectors.is_empty() => {
+                let i = random() % vectors.len();
+                vectors[i].extend(0..(random() % 64) as u64);
+            }
+            3 if !vectors.is_empty() => {
+                vectors.swap_remove(random() % vectors.len());
+            }
+            4 if step % 64 == 0 => {
+                let layout = Layout::from_size_align(256, 4096).unwrap();
+                blocks.push((a.alloq(layout), layout));
+            }
+            4..=5 if !blocks.is_empty() => {
+                let (ptr, layout) = blocks.swap_remove(random() % blocks.len());
+                unsafe { a.dealloq(ptr, layout) };
+            }
+            _ => {
+                let layout = Layout::from_size_align(1 + random() % 512, 8).unwrap();
+                blocks.push((a.alloq(layout), layout));
+            }
+        }
+    }
+    drop(vectors);
+    for (ptr, layout) in blocks {
+        unsafe { a.dealloq(ptr, layout) };
+    }
+    println!("recorded the workload in {:?}", start.elapsed());
+    std::mem::take(&mut EVENTS.lock().unwrap())
+}
+
+macro_rules! replay_all {
+    ($heap:expr, $ops:expr, $blocks:expr, $($name:expr => $alloq:expr),*) => {{
+        println!(
+            "{:<8} {:>14} {:>18} {:>14} {:>8}",
+            "alloq", "time", "peak live (bytes)", "span (bytes)", "failed"
+        );
+        $(
+            let r = replay($alloq, $heap.clone(), $ops, $blocks);
+            let span = r.span.map_or("-".to_string(), |span| span.to_string());
+            println!(
+                "{:<8} {:>14?} {:>18} {:>14} {:>8}",
+                $name, r.time, r.peak, span, r.failed
+            );
+        )*
+    }};
+}
+
+/// `cargo bench --bench replay -- [trace]`: replays the trace (a text file of `Event`s, as
+/// written by `TextSink`) or, without one, a recorded workload saved to `alloq-replay-*/trace`.
+fn main() {
+    let events: Vec<Event> = match env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        Some(path) => fs::read_to_string(&path)
+            .expect("can't read the trace")
+            .lines()
+            .map(|line| line.parse().expect("invalid trace line"))
+            .collect(),
+        None => {
+            let events = record_workload();
+            let mut hasher = DefaultHasher::new();
+            SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .expect("can't get time")
+                .hash(&mut hasher);
+            let dir = format!("alloq-replay-{}", hasher.finish());
+            fs::create_dir(&dir).expect("can't create a directory");
+            let text: String = events.iter().map(|event| format!("{event}\n")).collect();
+            fs::write(format!("{dir}/trace"), text).expect("can't write the trace");
+            println!("trace saved on {dir}/trace");
+            events
+        }
+    };
+    let (ops, blocks) = resolve(&events);
+    let requested = ops
+        .iter()
+        .filter(|op| matches!(op, Op::Alloc(..) | Op::Realloc(..)))
+        .count();
+    println!(
+        "replaying {} events ({requested} allocations/reallocations of {blocks} blocks)",
+        events.len()
+    );
+
+    let mut heap = vec![0u8; HEAP_SIZE];
+    let range = heap.as_mut_ptr_range();
+    replay_all!(range, &ops, blocks,
+        "bump" => &<bump::Alloq>::new(range.clone()),
+        "debump" => &<debump::Alloq>::new(range.clone()),
+        "first" => &first::Alloq::new(range.clone()),
+        "best" => &best::Alloq::new(range.clone()),
+        "pool" => &<pool::Alloq>::new(range.clone()),
+        "statiq" => &<statiq::Alloq>::new(range.clone()),
+        "system" => &system::Alloq::new(range.clone())
+    );
+}
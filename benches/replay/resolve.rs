@@ -0,0 +1,72 @@
+use std::{alloc::Layout, collections::HashMap};
+
+use alloq::trace::Event;
+
+/// A trace event with the recorded addresses replaced by block ids, so replaying it doesn't
+/// look them up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Alloc(usize, Layout),
+    Free(usize),
+    Realloc(usize, Layout),
+    Reset,
+}
+
+/// Gives an id to each block of the trace. A `Realloc` keeps the id of the block it moves.
+/// Zero-sized blocks share dangling addresses, so they can't be told apart: they're skipped, as
+/// replaying them wouldn't touch the heap anyway.
+pub fn resolve(events: &[Event]) -> (Vec<Op>, usize) {
+    let layout = |size, align| Layout::from_size_align(size, align).expect("invalid layout");
+    let mut ids = HashMap::new();
+    let mut blocks = 0;
+    let mut ops = Vec::with_capacity(events.len());
+    for &event in events {
+        match event {
+            Event::Alloc {
+                ptr, size, align, ..
+            } if ptr != 0 && size != 0 => {
+                ids.insert(ptr, blocks);
+                ops.push(Op::Alloc(blocks, layout(size, align)));
+                blocks += 1;
+            }
+            Event::Free { ptr, size, .. } if size != 0 => {
+                if let Some(id) = ids.remove(&ptr) {
+                    ops.push(Op::Free(id));
+                }
+            }
+            Event::Realloc {
+                ptr,
+                size,
+                new_ptr,
+                new_size,
+                new_align,
+                ..
+            } if new_ptr != 0 => match (size, new_size) {
+                (0, 0) => {}
+                // The zero-sized block wasn't replayed, so this one is new.
+                (0, _) => {
+                    ids.insert(new_ptr, blocks);
+                    ops.push(Op::Alloc(blocks, layout(new_size, new_align)));
+                    blocks += 1;
+                }
+                _ => {
+                    if let Some(id) = ids.remove(&ptr) {
+                        if new_size == 0 {
+                            ops.push(Op::Free(id));
+                        } else {
+                            ids.insert(new_ptr, id);
+                            ops.push(Op::Realloc(id, layout(new_size, new_align)));
+                        }
+                    }
+                }
+            },
+            Event::Reset { .. } => {
+                ids.clear();
+                ops.push(Op::Reset);
+            }
+            // Zero-sized, or failed in the recording, so they didn't change anything.
+            Event::Alloc { .. } | Event::Free { .. } | Event::Realloc { .. } => {}
+        }
+    }
+    (ops, blocks)
+}
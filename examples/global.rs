@@ -0,0 +1,33 @@
+//! Uses `alloq` as the global allocator of a regular program: everything `std` allocates comes
+//! from a static 1 MiB buffer managed by the first-fit list allocator.
+//! Run with `cargo run --example global --features list`.
+use alloq::{heap::Global, list};
+use std::{collections::BTreeMap, mem::MaybeUninit, thread};
+
+const HEAP_SIZE: usize = 1024 * 1024;
+
+static mut HEAP: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
+
+#[global_allocator]
+static GLOBAL: Global<list::first::Alloq> = unsafe { Global::from_static(&raw mut HEAP) };
+
+fn main() {
+    let words = thread::spawn(|| {
+        let mut count = BTreeMap::new();
+        for word in "the quick brown fox jumps over the lazy dog the end".split(' ') {
+            *count.entry(word.to_string()).or_insert(0) += 1;
+        }
+        count
+    })
+    .join()
+    .unwrap();
+    let v: Vec<u64> = (0..1000).collect();
+    let range = GLOBAL.heap_range();
+    assert!(range.contains(&v.as_ptr().cast_mut().cast()));
+    println!(
+        "{} distinct words, sum {}, heap at {:?}",
+        words.len(),
+        v.iter().sum::<u64>(),
+        range
+    );
+}
@@ -0,0 +1,32 @@
+#![no_main]
+
+use alloq::{
+    bump, cache, compose, debump,
+    list::{best, first},
+    model::{self, Ops},
+    pool, statiq, Alloqator,
+};
+use libfuzzer_sys::fuzz_target;
+
+const HEAP_SIZE: usize = 1 << 18;
+
+/// Runs the input on a new `A` over a new heap, panicking on a violation.
+fn check<A: Alloqator>(name: &str, bytes: &[u8]) {
+    let mut heap = vec![0u8; HEAP_SIZE];
+    let alloqer = A::new(heap.as_mut_ptr_range());
+    if let Err(violation) = model::check(&alloqer, Ops::new(bytes)) {
+        panic!("{name}: {violation}");
+    }
+}
+
+fuzz_target!(|bytes: &[u8]| {
+    check::<first::Alloq>("first", bytes);
+    check::<best::Alloq>("best", bytes);
+    check::<bump::Alloq>("bump", bytes);
+    check::<bump::atomic::Alloq>("atomic bump", bytes);
+    check::<debump::Alloq>("debump", bytes);
+    check::<pool::Alloq>("pool", bytes);
+    check::<statiq::Alloq>("statiq", bytes);
+    check::<cache::Alloq<first::Alloq>>("cache", bytes);
+    check::<compose::Segregator<64, bump::Alloq, first::Alloq>>("segregator", bytes);
+});
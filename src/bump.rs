@@ -3,49 +3,160 @@ use core::{
     ops::Range,
     ptr::NonNull,
 };
-use spin::Mutex;
 
-use crate::Alloqator;
+use crate::{
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
 
 /// A simple linear allocator. It just updates the stack's top for allocating and just resets it to
 /// stack's bottom when there are no current allocations.
 /// You shouldn't use it for long-lived allocations or for a global allocator, but it's a LOT
 /// faster for short-lived and tiny allocations
-pub struct Alloq {
+pub struct Alloq<L: RawLock = Spin> {
     pub heap_start: *mut u8,
-    pub iter: Mutex<(usize, *mut u8)>,
+    pub iter: Mutex<L, (usize, *mut u8)>,
     pub heap_end: *mut u8,
 }
 
-unsafe impl Allocator for Alloq {
+unsafe impl<L: RawLock> Allocator for Alloq<L> {
     /// Introducing an element is O(1). It just set the stack's top to the end of the allocated area
     /// and add 1 to the counter
     fn allocate(&self, layout: core::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let mut lock = self.iter.lock();
-        let start = crate::align_up(lock.1 as usize, layout.align()) as *mut u8;
-        let end = unsafe { start.add(layout.size()) };
-        if end > self.heap_end {
-            panic!("no available memory")
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
         }
-        lock.1 = end;
+        let mut lock = self.iter.acquire()?;
+        let start = crate::align_up(lock.1 as usize, layout.align());
+        let end = match start.checked_add(layout.size()) {
+            Some(end) if end <= self.heap_end as usize => end,
+            _ => return Err(AllocError),
+        };
+        let start = lock.1.with_addr(start);
+        lock.1 = lock.1.with_addr(end);
         lock.0 += 1;
         let slice = unsafe { core::slice::from_raw_parts_mut(start, layout.size()) };
         NonNull::new(slice).ok_or(AllocError)
     }
     /// Can't deallocate. Just reset. When the counter reaches 0, it reset the stack's top, since
     /// counter being 0 means that there is no value allocated
-    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
-        let mut lock = *self.iter.lock();
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        let mut lock = self.iter.lock();
         lock.0 -= 1;
         if lock.0 == 0 {
             lock.1 = self.heap_start;
         }
     }
+
+    /// In place if it's the last allocation (on the stack's top) and there's room.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+        if let Some(block) = self.resize_top(ptr, old_layout, new_layout)? {
+            return Ok(block);
+        }
+        crate::move_block(self, ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.grow(ptr, old_layout, new_layout)?;
+        let tail = block.cast::<u8>().add(old_layout.size());
+        tail.write_bytes(0, block.len() - old_layout.size());
+        Ok(block)
+    }
+
+    /// Always in place, but only the last allocation gives the memory back.
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(crate::dangling(new_layout));
+        }
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            return crate::move_block(self, ptr, old_layout, new_layout);
+        }
+        self.resize_top(ptr, old_layout, new_layout)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
+
+/// Blocks are just aligned one after the other, so it's where the stack's top ends.
+const fn required_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+    let mut top = 0usize;
+    let mut i = 0;
+    while i < layouts.len() {
+        let layout = match affixes.plan(layouts[i]) {
+            Some(layout) => layout,
+            None => return None,
+        };
+        i += 1;
+        if layout.size() == 0 {
+            continue;
+        }
+        top = match crate::align_up(top, layout.align()).checked_add(layout.size()) {
+            Some(top) => top,
+            None => return None,
+        };
+    }
+    Some(top)
+}
+
+impl<L: RawLock> Alloq<L> {
+    /// `Alloqator::required_heap_size`, but `const`.
+    pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        required_heap_size(layouts, Affixes::NONE)
+    }
+
+    /// Moves the stack's top if `ptr` is the last allocation, it's aligned to `new_layout` and
+    /// there's room. Returns `None` otherwise.
+    unsafe fn resize_top(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<Option<NonNull<[u8]>>, AllocError> {
+        let mut lock = self.iter.acquire()?;
+        let new_end = ptr.as_ptr().wrapping_add(new_layout.size());
+        if ptr.as_ptr().add(old_layout.size()) != lock.1
+            || !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align())
+            || new_end > self.heap_end
+        {
+            return Ok(None);
+        }
+        lock.1 = new_end;
+        Ok(Some(NonNull::slice_from_raw_parts(ptr, new_layout.size())))
+    }
 }
 
-impl Alloqator for Alloq {
+impl<L: RawLock> Alloqator for Alloq<L> {
     type Metadata = ();
 
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        required_heap_size(layouts, Affixes::NONE)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        required_heap_size(layouts, affixes)
+    }
+
     fn new(heap_range: Range<*mut u8>) -> Self {
         Self {
             heap_start: heap_range.start,
@@ -72,11 +183,224 @@ impl Alloqator for Alloq {
     }
 }
 
-crate::impl_allocator!(Alloq);
+crate::impl_allocator!(Alloq<L: RawLock>);
 
 #[cfg(test)]
 pub mod tests {
-    use super::Alloq;
+    type Alloq = super::Alloq;
+
+    const PLANS_HEAP: bool = true;
 
     include!("test.template.rs");
+
+    #[test]
+    fn const_heap_size() {
+        const SIZE: Option<usize> =
+            Alloq::required_heap_size(&[Layout::new::<u8>(), Layout::new::<[u32; 3]>()]);
+        assert_eq!(SIZE, Some(16));
+    }
+
+    #[test]
+    fn grow_in_place() {
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let mut v = Vec::with_capacity_in(4, &alloqer);
+        v.extend(0..4u32);
+        let ptr = v.as_ptr();
+        v.extend(4..128);
+        assert_eq!(v.as_ptr(), ptr, "the top allocation must grow in place");
+        let b = Box::new_in(0u32, &alloqer);
+        v.extend(128..200);
+        assert_ne!(v.as_ptr(), ptr, "only the top allocation can grow in place");
+        assert_eq!(v.iter().sum::<u32>(), (0..200).sum());
+        assert_eq!(*b, 0);
+    }
+}
+
+pub mod atomic {
+    use core::{
+        alloc::{AllocError, Allocator, Layout},
+        ops::Range,
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::{Affixes, Alloqator};
+
+    /// A lock-free version of `crate::bump::Alloq`. Instead of a `Mutex<(usize, *mut u8)>`, the
+    /// counter and the stack's top are packed in a single `AtomicUsize` and updated with a
+    /// compare-and-swap, so allocating never spins on a lock. The low `offset_bits` bits store the
+    /// top (as an offset from `heap_start`) and the high bits store the counter, so both are always
+    /// updated together and a deallocation can't reset the top under a concurrent allocation.
+    pub struct Alloq {
+        pub heap_start: *mut u8,
+        pub heap_end: *mut u8,
+        /// Number of bits needed to represent any offset in the heap. The remaining ones are used by
+        /// the counter.
+        pub offset_bits: u32,
+        pub state: AtomicUsize,
+    }
+
+    impl Alloq {
+        #[inline(always)]
+        fn offset_mask(&self) -> usize {
+            (1 << self.offset_bits) - 1
+        }
+
+        /// Maximum of simultaneous allocations supported by the counter.
+        #[inline(always)]
+        pub fn max_count(&self) -> usize {
+            usize::MAX >> self.offset_bits
+        }
+
+        /// Splits a state into (counter, top offset).
+        #[inline(always)]
+        pub fn unpack(&self, state: usize) -> (usize, usize) {
+            (state >> self.offset_bits, state & self.offset_mask())
+        }
+
+        #[inline(always)]
+        pub fn pack(&self, count: usize, offset: usize) -> usize {
+            (count << self.offset_bits) | offset
+        }
+
+        /// `Alloqator::required_heap_size`, but `const`. Same as `crate::bump::Alloq`'s.
+        pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+            super::required_heap_size(layouts, Affixes::NONE)
+        }
+    }
+
+    unsafe impl Allocator for Alloq {
+        /// Same as `crate::bump::Alloq::allocate`, but retries the compare-and-swap when another
+        /// thread moved the stack's top first.
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() == 0 {
+                return Ok(crate::dangling(layout));
+            }
+            let mut state = self.state.load(Ordering::Relaxed);
+            let start = loop {
+                let (count, offset) = self.unpack(state);
+                let start = crate::align_up(self.heap_start as usize + offset, layout.align());
+                let end = match start.checked_add(layout.size()) {
+                    Some(end) if end <= self.heap_end as usize => end,
+                    _ => return Err(AllocError),
+                };
+                if count >= self.max_count() {
+                    return Err(AllocError);
+                }
+                let new = self.pack(count + 1, end - self.heap_start as usize);
+                match self.state.compare_exchange_weak(
+                    state,
+                    new,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break self.heap_start.with_addr(start),
+                    Err(actual) => state = actual,
+                }
+            };
+            let slice = unsafe { core::slice::from_raw_parts_mut(start, layout.size()) };
+            NonNull::new(slice).ok_or(AllocError)
+        }
+
+        /// Decrements the counter and, if it reaches 0, resets the stack's top in the same atomic
+        /// operation.
+        unsafe fn deallocate(&self, _ptr: NonNull<u8>, layout: Layout) {
+            if layout.size() == 0 {
+                return;
+            }
+            let _ = self
+                .state
+                .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |state| {
+                    let (count, offset) = self.unpack(state);
+                    debug_assert!(count > 0, "deallocating from an empty bump");
+                    let count = count - 1;
+                    Some(self.pack(count, if count == 0 { 0 } else { offset }))
+                });
+        }
+    }
+
+    impl Alloqator for Alloq {
+        type Metadata = ();
+
+        fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+            super::required_heap_size(layouts, Affixes::NONE)
+        }
+
+        fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+            super::required_heap_size(layouts, affixes)
+        }
+
+        fn new(heap_range: Range<*mut u8>) -> Self {
+            let len = heap_range.end as usize - heap_range.start as usize;
+            Self {
+                heap_start: heap_range.start,
+                heap_end: heap_range.end,
+                offset_bits: usize::BITS - len.leading_zeros(),
+                state: AtomicUsize::new(0),
+            }
+        }
+
+        #[inline(always)]
+        fn heap_start(&self) -> *mut u8 {
+            self.heap_start
+        }
+
+        #[inline(always)]
+        fn heap_end(&self) -> *mut u8 {
+            self.heap_end
+        }
+
+        #[inline(always)]
+        unsafe fn reset(&self) {
+            self.state.store(0, Ordering::Release);
+        }
+    }
+
+    crate::impl_allocator!(Alloq);
+
+    #[cfg(test)]
+    pub mod tests {
+        use super::Alloq;
+        use core::sync::atomic::Ordering;
+
+        const PLANS_HEAP: bool = true;
+
+        include!("test.template.rs");
+
+        #[test]
+        fn contended_allocs() {
+            const THREADS: usize = 8;
+            const ALLOCS: usize = 256;
+            let mut heap = [0u8; THREADS * ALLOCS * 16];
+            let alloqer = Alloq::new(heap.as_mut_ptr_range());
+            let layout = Layout::new::<usize>();
+            thread::scope(|s| {
+                for t in 0..THREADS {
+                    let alloqer = &alloqer;
+                    s.spawn(move || {
+                        let ptrs: Vec<_> = (0..ALLOCS)
+                            .map(|i| {
+                                let ptr = alloqer.alloq(layout).cast::<usize>();
+                                unsafe { ptr.write(t * ALLOCS + i) };
+                                ptr
+                            })
+                            .collect();
+                        for (i, ptr) in ptrs.into_iter().enumerate() {
+                            assert_eq!(
+                                unsafe { ptr.read() },
+                                t * ALLOCS + i,
+                                "overlapped allocation"
+                            );
+                            unsafe { alloqer.dealloq(ptr.cast(), layout) };
+                        }
+                    });
+                }
+            });
+            assert_eq!(
+                alloqer.unpack(alloqer.state.load(Ordering::Relaxed)),
+                (0, 0)
+            );
+        }
+    }
 }
@@ -0,0 +1,331 @@
+pub use crate::lock::{CoreId, SingleCore};
+use crate::{
+    lock::{Mutex, RawLock, Spin},
+    Alloqator,
+};
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    marker::PhantomData,
+    mem,
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+/// Block size of the first size class. Each next class doubles it.
+pub const MIN_CLASS_SIZE: usize = 8;
+/// Number of size classes, from `MIN_CLASS_SIZE` to `MIN_CLASS_SIZE << (CLASSES - 1)` (1 KiB).
+pub const CLASSES: usize = 8;
+/// Maximum alignment of a cached block. Over-aligned layouts go straight to the backing
+/// allocator.
+pub const MAX_CLASS_ALIGN: usize = 2 * mem::size_of::<usize>();
+
+/// A fixed-capacity stack of free blocks of the same size class.
+#[derive(Debug)]
+pub struct Magazine<const CAPACITY: usize> {
+    pub blocks: [*mut u8; CAPACITY],
+    pub len: usize,
+}
+
+impl<const CAPACITY: usize> Magazine<CAPACITY> {
+    pub const fn new() -> Self {
+        Self {
+            blocks: [null_mut(); CAPACITY],
+            len: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.len == CAPACITY
+    }
+
+    #[inline(always)]
+    pub fn pop(&mut self) -> Option<*mut u8> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.blocks[self.len])
+    }
+
+    #[inline(always)]
+    pub fn push(&mut self, block: *mut u8) {
+        self.blocks[self.len] = block;
+        self.len += 1;
+    }
+}
+
+impl<const CAPACITY: usize> Default for Magazine<CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A magazine layer (like a tcache) in front of any `Alloqator`. Each core has its own magazines,
+/// one per size class, behind its own lock, so small allocations usually don't touch the backing
+/// allocator's lock at all. When a magazine is empty, it's refilled with `CAPACITY / 2` blocks
+/// from `backing`, and when it's full, half of it is flushed back.
+/// Layouts bigger than the last class or more aligned than `MAX_CLASS_ALIGN` aren't cached.
+/// `C::current()` must be lower than `CORES`. An allocation that re-enters a core's locked
+/// magazines fails with `AllocError` (see `RawLock::acquire`).
+pub struct Alloq<
+    A: Alloqator,
+    C: CoreId = SingleCore,
+    const CORES: usize = 1,
+    const CAPACITY: usize = 32,
+    L: RawLock = Spin,
+> {
+    pub backing: A,
+    pub cores: [Mutex<L, [Magazine<CAPACITY>; CLASSES]>; CORES],
+    pub _marker: PhantomData<C>,
+}
+
+unsafe impl<
+        A: Alloqator + Send,
+        C: CoreId,
+        const CORES: usize,
+        const CAPACITY: usize,
+        L: RawLock + Send,
+    > Send for Alloq<A, C, CORES, CAPACITY, L>
+{
+}
+unsafe impl<
+        A: Alloqator + Sync,
+        C: CoreId,
+        const CORES: usize,
+        const CAPACITY: usize,
+        L: RawLock + Sync,
+    > Sync for Alloq<A, C, CORES, CAPACITY, L>
+{
+}
+
+impl<A: Alloqator, C: CoreId, const CORES: usize, const CAPACITY: usize, L: RawLock>
+    Alloq<A, C, CORES, CAPACITY, L>
+{
+    /// Number of blocks moved from/to `backing` on each refill/flush.
+    pub const BATCH: usize = if CAPACITY > 1 { CAPACITY / 2 } else { 1 };
+
+    pub fn with_backing(backing: A) -> Self {
+        Self {
+            backing,
+            cores: core::array::from_fn(|_| Mutex::new(core::array::from_fn(|_| Magazine::new()))),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the size class of `layout`, or `None` if it isn't cached. Zero-sized layouts aren't
+    /// cached, `backing` already handles them without touching its state. Blocks of a class are
+    /// only aligned to their size, so the class is at least as big as the alignment.
+    pub fn class_of(layout: Layout) -> Option<usize> {
+        if layout.size() == 0 || layout.align() > MAX_CLASS_ALIGN {
+            return None;
+        }
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(MIN_CLASS_SIZE)
+            .next_power_of_two();
+        let class = (size / MIN_CLASS_SIZE).trailing_zeros() as usize;
+        (class < CLASSES).then_some(class)
+    }
+
+    /// The layout used to allocate blocks of `class` from `backing`.
+    pub fn class_layout(class: usize) -> Layout {
+        let size = MIN_CLASS_SIZE << class;
+        Layout::from_size_align(size, size.min(MAX_CLASS_ALIGN)).unwrap()
+    }
+
+    #[inline(always)]
+    fn magazines(&self) -> &Mutex<L, [Magazine<CAPACITY>; CLASSES]> {
+        let core = C::current();
+        assert!(core < CORES, "core {core} out of the cache ({CORES} cores)");
+        &self.cores[core]
+    }
+
+    /// Returns every cached block of every core to `backing`.
+    pub fn flush(&self) {
+        for magazines in self.cores.iter() {
+            let mut magazines = magazines.lock();
+            for (class, magazine) in magazines.iter_mut().enumerate() {
+                while let Some(block) = magazine.pop() {
+                    unsafe { self.backing.dealloq(block, Self::class_layout(class)) };
+                }
+            }
+        }
+    }
+}
+
+unsafe impl<A: Alloqator, C: CoreId, const CORES: usize, const CAPACITY: usize, L: RawLock>
+    Allocator for Alloq<A, C, CORES, CAPACITY, L>
+{
+    /// Pops a block from the current core's magazine, refilling it from `backing` if empty.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let Some(class) = Self::class_of(layout) else {
+            return self.backing.allocate(layout);
+        };
+        let class_layout = Self::class_layout(class);
+        let mut magazines = self.magazines().acquire()?;
+        let magazine = &mut magazines[class];
+        if magazine.len == 0 {
+            for _ in 0..Self::BATCH {
+                match self.backing.allocate(class_layout) {
+                    Ok(block) => magazine.push(block.as_ptr().cast()),
+                    Err(_) if magazine.len != 0 => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        let block = magazine.pop().unwrap();
+        let slice = unsafe { core::slice::from_raw_parts_mut(block, class_layout.size()) };
+        NonNull::new(slice).ok_or(AllocError)
+    }
+
+    /// Pushes the block to the current core's magazine, flushing half of it to `backing` if full.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let Some(class) = Self::class_of(layout) else {
+            return self.backing.deallocate(ptr, layout);
+        };
+        let mut magazines = self.magazines().lock();
+        let magazine = &mut magazines[class];
+        if magazine.is_full() {
+            for _ in 0..Self::BATCH {
+                let block = magazine.pop().unwrap();
+                self.backing.dealloq(block, Self::class_layout(class));
+            }
+        }
+        magazine.push(ptr.as_ptr());
+    }
+}
+
+impl<A: Alloqator, C: CoreId, const CORES: usize, const CAPACITY: usize, L: RawLock> Alloqator
+    for Alloq<A, C, CORES, CAPACITY, L>
+{
+    type Metadata = A::Metadata;
+
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        Self::with_backing(A::new(heap_range))
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        self.backing.heap_start()
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        self.backing.heap_end()
+    }
+
+    /// Drops every cached block (without returning them) and resets `backing`.
+    unsafe fn reset(&self) {
+        for magazines in self.cores.iter() {
+            for magazine in magazines.lock().iter_mut() {
+                magazine.len = 0;
+            }
+        }
+        self.backing.reset();
+    }
+    /// The size of the class, since cached blocks are handed out again for any layout in it.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        match Self::class_of(layout) {
+            Some(class) => Self::class_layout(class).size(),
+            None => self.backing.usable_size(ptr, layout),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "list"))]
+pub mod tests {
+    use super::{CoreId, SingleCore};
+
+    type Alloq = super::Alloq<crate::list::first::Alloq, SingleCore, 1, 2>;
+
+    // Refills take batches that can partly fail, so it can't be planned.
+    const PLANS_HEAP: bool = false;
+
+    include!("test.template.rs");
+
+    struct ThreadCore;
+
+    std::thread_local! {
+        static CORE: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+    }
+
+    impl CoreId for ThreadCore {
+        fn current() -> usize {
+            CORE.with(|c| c.get())
+        }
+    }
+
+    #[test]
+    fn refills_in_batches() {
+        type Cache = super::Alloq<crate::list::first::Alloq, SingleCore, 1, 8>;
+        let mut heap = [0u8; 1024 * 8];
+        let alloqer = Cache::new(heap.as_mut_ptr_range());
+        let layout = Layout::new::<u64>();
+        let ptr = alloqer.alloq(layout);
+        assert_eq!(alloqer.cores[0].lock()[0].len, Cache::BATCH - 1);
+        unsafe { alloqer.dealloq(ptr, layout) };
+        assert_eq!(alloqer.cores[0].lock()[0].len, Cache::BATCH);
+        let ptrs: Vec<_> = (0..Cache::BATCH).map(|_| alloqer.alloq(layout)).collect();
+        assert_eq!(ptrs[0], ptr, "a freed block must be reused first");
+        assert_eq!(alloqer.cores[0].lock()[0].len, 0);
+        for ptr in ptrs {
+            unsafe { alloqer.dealloq(ptr, layout) };
+        }
+        alloqer.flush();
+        assert_eq!(alloqer.cores[0].lock()[0].len, 0);
+    }
+
+    #[test]
+    fn uncached_layouts() {
+        let mut heap = [0u8; 1024 * 8];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let big = Layout::from_size_align(2048, 8).unwrap();
+        let aligned = Layout::from_size_align(8, 64).unwrap();
+        assert_eq!(Alloq::class_of(big), None);
+        assert_eq!(Alloq::class_of(aligned), None);
+        let (b, a) = (alloqer.alloq(big), alloqer.alloq(aligned));
+        assert_eq!(a as usize % 64, 0);
+        unsafe {
+            alloqer.dealloq(b, big);
+            alloqer.dealloq(a, aligned);
+        }
+        assert!(alloqer.cores[0].lock().iter().all(|m| m.len == 0));
+    }
+
+    #[test]
+    fn aligned_classes() {
+        let mut heap = [0u8; 1024 * 8];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let layout = Layout::from_size_align(7, 16).unwrap();
+        assert_eq!(Alloq::class_of(layout), Some(1));
+        let ptrs: Vec<_> = (0..4).map(|_| alloqer.alloq(layout)).collect();
+        assert!(ptrs.iter().all(|&ptr| (ptr as usize).is_multiple_of(16)));
+        for ptr in ptrs {
+            unsafe { alloqer.dealloq(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn per_core_threads() {
+        const CORES: usize = 4;
+        const ALLOCS: usize = 128;
+        type Cache = super::Alloq<crate::list::first::Alloq, ThreadCore, CORES, 16>;
+        let mut heap = [0u8; 1024 * 256];
+        let alloqer = Cache::new(heap.as_mut_ptr_range());
+        thread::scope(|s| {
+            for core in 0..CORES {
+                let alloqer = &alloqer;
+                s.spawn(move || {
+                    CORE.with(|c| c.set(core));
+                    let mut v = Vec::new_in(alloqer);
+                    let boxes: Vec<_> = (0..ALLOCS)
+                        .map(|i| Box::new_in(core * ALLOCS + i, alloqer))
+                        .collect();
+                    for (i, b) in boxes.iter().enumerate() {
+                        assert_eq!(**b, core * ALLOCS + i, "overlapped allocation");
+                        v.push(i);
+                    }
+                    assert_eq!(v.iter().sum::<usize>(), (0..ALLOCS).sum());
+                });
+            }
+        });
+        alloqer.flush();
+    }
+}
@@ -0,0 +1,1142 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    marker::PhantomData,
+    mem,
+    ops::{Deref, Range},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{Affixes, Alloqator};
+
+/// Tries `primary` and, when it's out of memory, `secondary`. Deallocations go to whoever owns
+/// the pointer, checked by `primary.heap_range()`, so `secondary` can be an allocator without a
+/// heap range, like `crate::system::Alloq`.
+pub struct Fallback<P: Alloqator, S: Alloqator> {
+    pub primary: P,
+    pub secondary: S,
+}
+
+impl<P: Alloqator, S: Alloqator> Fallback<P, S> {
+    pub const fn with(primary: P, secondary: S) -> Self {
+        Self { primary, secondary }
+    }
+
+    /// Returns if `ptr` was allocated by `primary`.
+    #[inline(always)]
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        self.primary.heap_range().contains(&ptr.cast_mut())
+    }
+}
+
+unsafe impl<P: Alloqator, S: Alloqator> Allocator for Fallback<P, S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.primary
+            .allocate(layout)
+            .or_else(|_| self.secondary.allocate(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.owns(ptr.as_ptr()) {
+            self.primary.deallocate(ptr, layout)
+        } else {
+            self.secondary.deallocate(ptr, layout)
+        }
+    }
+}
+
+impl<P: Alloqator, S: Alloqator> Alloqator for Fallback<P, S> {
+    // `secondary` must fit whatever `primary` couldn't, so size hints follow it.
+    type Metadata = S::Metadata;
+
+    /// Splits the heap in half, the lower one for `primary`.
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        let (primary, secondary) = split(heap_range);
+        Self::with(P::new(primary), S::new(secondary))
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        span(self.primary.heap_range(), self.secondary.heap_range()).start
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        span(self.primary.heap_range(), self.secondary.heap_range()).end
+    }
+
+    unsafe fn reset(&self) {
+        self.primary.reset();
+        self.secondary.reset();
+    }
+
+    /// Resets both heaps, without touching what's between them.
+    unsafe fn hard_reset(&self) {
+        self.primary.hard_reset();
+        self.secondary.hard_reset();
+    }
+
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if self.owns(ptr.as_ptr()) {
+            self.primary.usable_size(ptr, layout)
+        } else {
+            self.secondary.usable_size(ptr, layout)
+        }
+    }
+}
+
+crate::impl_allocator!(Fallback<P: Alloqator, S: Alloqator>);
+
+/// Sends layouts up to `THRESHOLD` bytes (and aligned up to `SMALL_ALIGN`) to `small`, and the
+/// others to `large`. A `grow` or `shrink` that crosses the threshold moves the block between
+/// them.
+pub struct Segregator<
+    const THRESHOLD: usize,
+    S: Alloqator,
+    L: Alloqator,
+    const SMALL_ALIGN: usize = { usize::MAX },
+> {
+    pub small: S,
+    pub large: L,
+}
+
+unsafe impl<
+        const THRESHOLD: usize,
+        S: Alloqator + Send,
+        L: Alloqator + Send,
+        const SMALL_ALIGN: usize,
+    > Send for Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+}
+unsafe impl<
+        const THRESHOLD: usize,
+        S: Alloqator + Sync,
+        L: Alloqator + Sync,
+        const SMALL_ALIGN: usize,
+    > Sync for Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+}
+
+impl<const THRESHOLD: usize, S: Alloqator, L: Alloqator, const SMALL_ALIGN: usize>
+    Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+    pub const fn with(small: S, large: L) -> Self {
+        Self { small, large }
+    }
+
+    #[inline(always)]
+    pub const fn is_small(layout: Layout) -> bool {
+        layout.size() <= THRESHOLD && layout.align() <= SMALL_ALIGN
+    }
+
+    #[inline(always)]
+    fn pick(&self, layout: Layout) -> &dyn Allocator {
+        if Self::is_small(layout) {
+            &self.small
+        } else {
+            &self.large
+        }
+    }
+
+    /// Cuts the slack of small blocks at `THRESHOLD`, so that a layout fitting the whole block
+    /// still picks `small`.
+    #[inline(always)]
+    fn clamp(layout: Layout, block: NonNull<[u8]>) -> NonNull<[u8]> {
+        if Self::is_small(layout) && block.len() > THRESHOLD {
+            NonNull::slice_from_raw_parts(block.cast(), THRESHOLD)
+        } else {
+            block
+        }
+    }
+
+    /// Moves a block to the other side of the threshold, copying `min(old, new)` bytes.
+    /// # Safety
+    /// Same as `Allocator::grow`, but `old_layout` and `new_layout` must be on different sides.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let to = self.pick(new_layout);
+        let new = if zeroed {
+            to.allocate_zeroed(new_layout)?
+        } else {
+            to.allocate(new_layout)?
+        };
+        let count = old_layout.size().min(new_layout.size());
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr().cast(), count);
+        self.pick(old_layout).deallocate(ptr, old_layout);
+        Ok(Self::clamp(new_layout, new))
+    }
+}
+
+unsafe impl<const THRESHOLD: usize, S: Alloqator, L: Alloqator, const SMALL_ALIGN: usize> Allocator
+    for Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.pick(layout).allocate(layout)?;
+        Ok(Self::clamp(layout, block))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.pick(layout).allocate_zeroed(layout)?;
+        Ok(Self::clamp(layout, block))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.pick(layout).deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_small(old_layout) == Self::is_small(new_layout) {
+            let block = self.pick(old_layout).grow(ptr, old_layout, new_layout)?;
+            Ok(Self::clamp(new_layout, block))
+        } else {
+            self.reallocate(ptr, old_layout, new_layout, false)
+        }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_small(old_layout) == Self::is_small(new_layout) {
+            let block = self
+                .pick(old_layout)
+                .grow_zeroed(ptr, old_layout, new_layout)?;
+            Ok(Self::clamp(new_layout, block))
+        } else {
+            self.reallocate(ptr, old_layout, new_layout, true)
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_small(old_layout) == Self::is_small(new_layout) {
+            let block = self.pick(old_layout).shrink(ptr, old_layout, new_layout)?;
+            Ok(Self::clamp(new_layout, block))
+        } else {
+            self.reallocate(ptr, old_layout, new_layout, false)
+        }
+    }
+}
+
+impl<const THRESHOLD: usize, S: Alloqator, L: Alloqator, const SMALL_ALIGN: usize> Alloqator
+    for Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+    type Metadata = L::Metadata;
+
+    /// Splits the heap in half, the lower one for `small`.
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        let (small, large) = split(heap_range);
+        Self::with(S::new(small), L::new(large))
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        span(self.small.heap_range(), self.large.heap_range()).start
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        span(self.small.heap_range(), self.large.heap_range()).end
+    }
+
+    unsafe fn reset(&self) {
+        self.small.reset();
+        self.large.reset();
+    }
+
+    /// Resets both heaps, without touching what's between them.
+    unsafe fn hard_reset(&self) {
+        self.small.hard_reset();
+        self.large.hard_reset();
+    }
+
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if Self::is_small(layout) {
+            self.small.usable_size(ptr, layout).min(THRESHOLD)
+        } else {
+            self.large.usable_size(ptr, layout)
+        }
+    }
+}
+
+unsafe impl<const THRESHOLD: usize, S: Alloqator, L: Alloqator, const SMALL_ALIGN: usize>
+    core::alloc::GlobalAlloc for Segregator<THRESHOLD, S, L, SMALL_ALIGN>
+{
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        crate::AsGlobal::from_ref(self).alloc(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        crate::AsGlobal::from_ref(self).dealloc(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        crate::AsGlobal::from_ref(self).alloc_zeroed(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        crate::AsGlobal::from_ref(self).realloc(ptr, layout, new_size)
+    }
+}
+
+/// Reserves a `P` right before and a `S` after each allocation of `A`, e.g. for refcounts, type
+/// tags or canaries. They aren't initialized: write them through `prefix` and `suffix`.
+/// `grow` and `shrink` keep both.
+pub struct Affix<A: Alloqator, P = (), S = ()> {
+    pub inner: A,
+    pub _marker: PhantomData<(P, S)>,
+}
+
+impl<A: Alloqator, P, S> Affix<A, P, S> {
+    pub const fn with(inner: A) -> Self {
+        Self {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the layout requested to `inner` for `layout`, the offset of the user's block and
+    /// the offset of the suffix.
+    pub const fn outer_layout(layout: Layout) -> Option<(Layout, usize, usize)> {
+        Affixes::of::<P, S>().outer(layout)
+    }
+
+    /// The prefix of the block `ptr`.
+    /// # Safety
+    /// `ptr` must be allocated by this allocator, with a non-zero size. Zero-sized blocks don't
+    /// touch `inner`, so they have no prefix nor suffix.
+    #[inline(always)]
+    pub unsafe fn prefix(ptr: NonNull<u8>) -> NonNull<P> {
+        ptr.sub(mem::size_of::<P>()).cast()
+    }
+
+    /// The suffix of the block `ptr`, allocated with `layout`.
+    /// # Safety
+    /// `ptr` must be allocated by this allocator with `layout`, of a non-zero size.
+    #[inline(always)]
+    pub unsafe fn suffix(ptr: NonNull<u8>, layout: Layout) -> NonNull<S> {
+        let (_, offset, suffix) = Self::outer_layout(layout).unwrap_unchecked();
+        ptr.sub(offset).add(suffix).cast()
+    }
+
+    /// Moves the block, with its prefix and suffix, to a new one of `new_layout`.
+    /// # Safety
+    /// Same as `Allocator::grow`.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(crate::dangling(new_layout));
+        }
+        let new = if zeroed {
+            self.allocate_zeroed(new_layout)?
+        } else {
+            self.allocate(new_layout)?
+        };
+        if old_layout.size() == 0 {
+            return Ok(new);
+        }
+        let new_ptr = new.cast::<u8>();
+        let count = old_layout.size().min(new_layout.size());
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), count);
+        ptr::copy_nonoverlapping(
+            Self::prefix(ptr).as_ptr(),
+            Self::prefix(new_ptr).as_ptr(),
+            1,
+        );
+        ptr::copy_nonoverlapping(
+            Self::suffix(ptr, old_layout).as_ptr(),
+            Self::suffix(new_ptr, new_layout).as_ptr(),
+            1,
+        );
+        self.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+}
+
+unsafe impl<A: Alloqator, P, S> Allocator for Affix<A, P, S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
+        }
+        let (outer, offset, _) = Self::outer_layout(layout).ok_or(AllocError)?;
+        let block = self.inner.allocate(outer)?.cast::<u8>();
+        let ptr = unsafe { block.add(offset) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        let (outer, offset, _) = Self::outer_layout(layout).unwrap_unchecked();
+        self.inner.deallocate(ptr.sub(offset), outer)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reallocate(ptr, old_layout, new_layout, false)
+    }
+}
+
+impl<A: Alloqator, P, S> Alloqator for Affix<A, P, S> {
+    // So heaps are also aligned for the prefix and the suffix.
+    type Metadata = (A::Metadata, P, S);
+
+    /// `A`'s for the outer layouts. They're mapped on the stack, so it plans at most 64 blocks
+    /// that aren't zero-sized.
+    /// `A`'s for the outer layouts. Affixes can't be nested in a single plan, so an `Affix`
+    /// inside another one can't plan its heap.
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        A::required_affixed_heap_size(layouts, Affixes::of::<P, S>())
+    }
+
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        Self::with(A::new(heap_range))
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        self.inner.heap_start()
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        self.inner.heap_end()
+    }
+
+    unsafe fn reset(&self) {
+        self.inner.reset()
+    }
+}
+
+crate::impl_allocator!(Affix<A: Alloqator, P: Sized, S: Sized>);
+
+/// A watermark crossed by a `Budget`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watermark {
+    /// The outstanding bytes went over the soft limit. The allocation still succeeds.
+    Soft,
+    /// An allocation would go over the limit, so it fails.
+    Hard,
+}
+
+/// Caps the bytes outstanding in `A`: once an allocation would exceed `limit`, it fails with
+/// `AllocError`. Sizes are the ones requested in the layouts, so the allocator's own metadata
+/// and padding aren't counted, and blocks are returned without `A`'s slack.
+pub struct Budget<A: Alloqator> {
+    pub inner: A,
+    pub limit: usize,
+    pub soft_limit: usize,
+    /// Called with the watermark and the outstanding bytes it would reach.
+    pub on_watermark: Option<fn(Watermark, usize)>,
+    pub used: AtomicUsize,
+    pub peak: AtomicUsize,
+}
+
+impl<A: Alloqator> Budget<A> {
+    pub const fn with(inner: A, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            soft_limit: usize::MAX,
+            on_watermark: None,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub const fn soft_limit(mut self, soft_limit: usize) -> Self {
+        self.soft_limit = soft_limit;
+        self
+    }
+
+    #[inline(always)]
+    pub const fn on_watermark(mut self, callback: fn(Watermark, usize)) -> Self {
+        self.on_watermark = Some(callback);
+        self
+    }
+
+    /// Bytes currently allocated.
+    #[inline(always)]
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Most bytes allocated at once since the creation or the last `reset_peak`.
+    #[inline(always)]
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn reset_peak(&self) {
+        self.peak.store(self.used(), Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    fn notify(&self, watermark: Watermark, used: usize) {
+        if let Some(callback) = self.on_watermark {
+            callback(watermark, used);
+        }
+    }
+
+    /// Takes `size` bytes from the budget, failing if the limit would be exceeded.
+    pub fn reserve(&self, size: usize) -> Result<(), AllocError> {
+        let reserved = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|&new| new <= self.limit)
+            });
+        match reserved {
+            Ok(old) => {
+                let new = old + size;
+                self.peak.fetch_max(new, Ordering::Relaxed);
+                if old <= self.soft_limit && new > self.soft_limit {
+                    self.notify(Watermark::Soft, new);
+                }
+                Ok(())
+            }
+            Err(used) => {
+                self.notify(Watermark::Hard, used.saturating_add(size));
+                Err(AllocError)
+            }
+        }
+    }
+
+    /// Gives `size` bytes back to the budget.
+    #[inline(always)]
+    pub fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: Alloqator> Allocator for Budget<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.reserve(layout.size())?;
+        self.inner
+            .allocate(layout)
+            .map(|block| exact(block, layout))
+            .inspect_err(|_| self.release(layout.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.reserve(layout.size())?;
+        self.inner
+            .allocate_zeroed(layout)
+            .map(|block| exact(block, layout))
+            .inspect_err(|_| self.release(layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.deallocate(ptr, layout);
+        self.release(layout.size());
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let extra = new_layout.size() - old_layout.size();
+        self.reserve(extra)?;
+        self.inner
+            .grow(ptr, old_layout, new_layout)
+            .map(|block| exact(block, new_layout))
+            .inspect_err(|_| self.release(extra))
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let extra = new_layout.size() - old_layout.size();
+        self.reserve(extra)?;
+        self.inner
+            .grow_zeroed(ptr, old_layout, new_layout)
+            .map(|block| exact(block, new_layout))
+            .inspect_err(|_| self.release(extra))
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.inner.shrink(ptr, old_layout, new_layout)?;
+        self.release(old_layout.size() - new_layout.size());
+        Ok(exact(block, new_layout))
+    }
+}
+
+/// `block` cut to the size of `layout`, for wrappers that can't let the caller use the slack.
+#[inline(always)]
+fn exact(block: NonNull<[u8]>, layout: Layout) -> NonNull<[u8]> {
+    NonNull::slice_from_raw_parts(block.cast(), layout.size())
+}
+
+impl<A: Alloqator> Alloqator for Budget<A> {
+    type Metadata = A::Metadata;
+
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        A::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        A::required_affixed_heap_size(layouts, affixes)
+    }
+
+    /// Without a limit, it just tracks the usage.
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        Self::with(A::new(heap_range), usize::MAX)
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        self.inner.heap_start()
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        self.inner.heap_end()
+    }
+
+    /// Resets `inner` and the usage. The peak is kept.
+    unsafe fn reset(&self) {
+        self.inner.reset();
+        self.used.store(0, Ordering::Relaxed);
+    }
+}
+
+crate::impl_allocator!(Budget<A: Alloqator>);
+
+/// A child heap carved from a block of `parent`: `child` is built over the block with
+/// `Alloqator::new`, and the block is given back to `parent` when dropped.
+pub struct SubHeap<'a, P: Alloqator, C: Alloqator> {
+    pub parent: &'a P,
+    pub child: C,
+    block: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl<P: Alloqator + Sync, C: Alloqator + Send> Send for SubHeap<'_, P, C> {}
+unsafe impl<P: Alloqator + Sync, C: Alloqator + Sync> Sync for SubHeap<'_, P, C> {}
+
+impl<'a, P: Alloqator, C: Alloqator> SubHeap<'a, P, C> {
+    /// Allocates a block of `layout` from `parent` and builds `C` over it.
+    pub fn new(parent: &'a P, layout: Layout) -> Result<Self, AllocError> {
+        let block = parent.allocate(layout)?;
+        let start = block.cast::<u8>().as_ptr();
+        let child = C::new(start..unsafe { start.add(block.len()) });
+        Ok(Self {
+            parent,
+            child,
+            block: block.cast(),
+            layout,
+        })
+    }
+
+    /// Same as `new`, with a block of `size` bytes aligned to `C::Metadata`.
+    pub fn with_size(parent: &'a P, size: usize) -> Result<Self, AllocError> {
+        let layout = Layout::from_size_align(size, mem::align_of::<C::Metadata>().max(1))
+            .map_err(|_| AllocError)?;
+        Self::new(parent, layout)
+    }
+
+    /// The block taken from `parent`.
+    #[inline(always)]
+    pub fn block(&self) -> (NonNull<u8>, Layout) {
+        (self.block, self.layout)
+    }
+}
+
+impl<P: Alloqator, C: Alloqator> Deref for SubHeap<'_, P, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.child
+    }
+}
+
+unsafe impl<P: Alloqator, C: Alloqator> Allocator for SubHeap<'_, P, C> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.child.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.child.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.child.grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.child.shrink(ptr, old_layout, new_layout)
+    }
+}
+
+impl<P: Alloqator, C: Alloqator> Drop for SubHeap<'_, P, C> {
+    /// Gives the block back to `parent`. As `Box` or `Vec` in `child` borrow the sub-heap,
+    /// they can't outlive it.
+    fn drop(&mut self) {
+        unsafe { self.parent.deallocate(self.block, self.layout) }
+    }
+}
+
+/// The smallest range holding both `a` and `b`, ignoring an empty one, like the range of an
+/// allocator without a heap.
+pub fn span(a: Range<*mut u8>, b: Range<*mut u8>) -> Range<*mut u8> {
+    if a.is_empty() {
+        b
+    } else if b.is_empty() {
+        a
+    } else {
+        a.start.min(b.start)..a.end.max(b.end)
+    }
+}
+
+/// Splits a heap in two halves.
+pub fn split(heap_range: Range<*mut u8>) -> (Range<*mut u8>, Range<*mut u8>) {
+    let mid = unsafe {
+        heap_range
+            .start
+            .add(heap_range.end.offset_from(heap_range.start) as usize / 2)
+    };
+    (heap_range.start..mid, mid..heap_range.end)
+}
+
+#[cfg(test)]
+pub mod tests {
+    extern crate alloc;
+
+    #[cfg(all(feature = "bump", feature = "list"))]
+    pub mod fallback {
+        type Alloq = super::super::Fallback<crate::bump::Alloq, crate::list::first::Alloq>;
+
+        // Which half a block ends in depends on where the first one runs out.
+        const PLANS_HEAP: bool = false;
+
+        include!("test.template.rs");
+    }
+
+    #[cfg(all(feature = "bump", feature = "list"))]
+    pub mod segregator {
+        type Alloq = super::super::Segregator<64, crate::bump::Alloq, crate::list::first::Alloq>;
+
+        // The upper half starts mid-heap, at an alignment that depends on the heap's size.
+        const PLANS_HEAP: bool = false;
+
+        include!("test.template.rs");
+    }
+
+    #[cfg(feature = "list")]
+    pub mod affix {
+        type Alloq = super::super::Affix<crate::list::first::Alloq, u64, u32>;
+
+        const PLANS_HEAP: bool = true;
+
+        include!("test.template.rs");
+    }
+
+    #[cfg(feature = "list")]
+    pub mod budget {
+        type Alloq = super::super::Budget<crate::list::first::Alloq>;
+
+        const PLANS_HEAP: bool = true;
+
+        include!("test.template.rs");
+    }
+
+    #[test]
+    #[cfg(all(feature = "bump", feature = "list"))]
+    fn bump_then_list() {
+        use super::Fallback;
+        use crate::Alloqator;
+        use alloc::vec::Vec;
+        use core::alloc::Layout;
+
+        type Alloq = Fallback<crate::bump::Alloq, crate::list::first::Alloq>;
+        let mut arena = [0u8; 64];
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::with(
+            <crate::bump::Alloq>::new(arena.as_mut_ptr_range()),
+            crate::list::first::Alloq::new(heap.as_mut_ptr_range()),
+        );
+        let layout = Layout::new::<u64>();
+        let ptrs: Vec<_> = (0..16).map(|_| alloqer.alloq(layout)).collect();
+        assert_eq!(ptrs.iter().filter(|&&p| alloqer.owns(p)).count(), 8);
+        assert!(ptrs[8..]
+            .iter()
+            .all(|&p| heap.as_ptr_range().contains(&p.cast_const())));
+        for ptr in ptrs {
+            unsafe { alloqer.dealloq(ptr, layout) };
+        }
+        // Everything was returned, so the arena is empty again.
+        assert!(alloqer.owns(alloqer.alloq(Layout::new::<[u64; 8]>())));
+    }
+
+    #[test]
+    #[cfg(all(feature = "pool", feature = "system"))]
+    fn pool_then_system() {
+        use super::Fallback;
+        use crate::Alloqator;
+        use alloc::vec::Vec;
+
+        type Alloq = Fallback<crate::pool::Alloq, crate::system::Alloq>;
+        let mut heap = [0u8; 1024];
+        let alloqer = Alloq::with(
+            <crate::pool::Alloq>::new(heap.as_mut_ptr_range()),
+            crate::system::Alloq::new(heap.as_mut_ptr_range()),
+        );
+        let mut v = Vec::new();
+        for i in 0..64u64 {
+            let b = alloc::boxed::Box::new_in(i, &alloqer);
+            v.push(b);
+        }
+        let owned = v
+            .iter()
+            .filter(|b| alloqer.owns((&***b as *const u64).cast()))
+            .count();
+        assert!(owned > 0 && owned < v.len(), "{owned} of {}", v.len());
+        assert!(v.iter().enumerate().all(|(i, b)| **b == i as u64));
+        drop(v);
+        let mut w = Vec::new_in(&alloqer);
+        w.extend(0..1024u32);
+        assert_eq!(w.iter().sum::<u32>(), (0..1024).sum());
+    }
+
+    #[test]
+    #[cfg(all(feature = "pool", feature = "system"))]
+    fn system_without_heap() {
+        use super::Fallback;
+        use crate::Alloqator;
+        use core::alloc::Layout;
+
+        type Alloq = Fallback<crate::pool::Alloq, crate::system::Alloq>;
+        let mut heap = [0u8; 1024];
+        let alloqer = Alloq::with(
+            <crate::pool::Alloq>::new(heap.as_mut_ptr_range()),
+            crate::system::Alloq::new(heap.as_mut_ptr_range()),
+        );
+        // `system` has no heap, so the range is the pool's.
+        assert!(alloqer.secondary.heap_range().is_empty());
+        assert_eq!(alloqer.heap_range(), heap.as_mut_ptr_range());
+        let layout = Layout::new::<[u8; 2048]>();
+        let ptr = alloqer.alloq(layout);
+        assert!(!ptr.is_null() && !alloqer.owns(ptr));
+        unsafe {
+            alloqer.dealloq(ptr, layout);
+            alloqer.hard_reset();
+        }
+        assert!(alloqer.owns(alloqer.alloq(Layout::new::<u64>())));
+    }
+
+    #[test]
+    #[cfg(all(feature = "pool", feature = "list"))]
+    fn segregate_by_size() {
+        use super::Segregator;
+        use crate::Alloqator;
+        use core::alloc::Layout;
+
+        type Alloq = Segregator<64, crate::pool::Alloq, crate::list::first::Alloq, 16>;
+        let mut heap = [0u8; 1024 * 8];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let small = alloqer.alloq(Layout::new::<[u8; 64]>());
+        let large = alloqer.alloq(Layout::new::<[u8; 65]>());
+        let aligned = alloqer.alloq(Layout::from_size_align(8, 32).unwrap());
+        assert!(alloqer.small.heap_range().contains(&small));
+        assert!(alloqer.large.heap_range().contains(&large));
+        assert!(alloqer.large.heap_range().contains(&aligned));
+        assert_eq!(aligned as usize % 32, 0);
+        unsafe {
+            alloqer.dealloq(small, Layout::new::<[u8; 64]>());
+            alloqer.dealloq(large, Layout::new::<[u8; 65]>());
+            alloqer.dealloq(aligned, Layout::from_size_align(8, 32).unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pool", feature = "list"))]
+    fn grow_across_threshold() {
+        use super::Segregator;
+        use crate::Alloqator;
+        use alloc::vec::Vec;
+
+        type Alloq = Segregator<64, crate::pool::Alloq, crate::list::first::Alloq>;
+        let mut heap = [0u8; 1024 * 8];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let mut v = Vec::new_in(&alloqer);
+        v.push(0u8);
+        assert!(alloqer.small.heap_range().contains(&v.as_mut_ptr()));
+        v.extend(1..=255);
+        assert!(alloqer.large.heap_range().contains(&v.as_mut_ptr()));
+        v.truncate(16);
+        v.shrink_to_fit();
+        assert!(alloqer.small.heap_range().contains(&v.as_mut_ptr()));
+        assert!(v.iter().enumerate().all(|(i, &x)| x == i as u8));
+    }
+
+    #[test]
+    #[cfg(feature = "list")]
+    fn affix_layout() {
+        use super::Affix;
+        use core::alloc::Layout;
+
+        type Alloq = Affix<crate::list::first::Alloq, [u16; 3], u128>;
+        let (outer, offset, suffix) = Alloq::outer_layout(Layout::new::<u8>()).unwrap();
+        assert_eq!((offset, suffix), (6, 16));
+        assert_eq!(outer, Layout::from_size_align(32, 16).unwrap());
+        let (outer, offset, suffix) = Alloq::outer_layout(Layout::new::<[u64; 4]>()).unwrap();
+        assert_eq!((offset, suffix), (8, 48));
+        assert_eq!(outer, Layout::from_size_align(64, 16).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "list")]
+    fn affix_headers() {
+        use super::Affix;
+        use crate::Alloqator;
+        use alloc::vec::Vec;
+        use core::alloc::Layout;
+
+        type Alloq = Affix<crate::list::first::Alloq, u32, u16>;
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let mut v = Vec::with_capacity_in(1, &alloqer);
+        v.push(7u64);
+        let layout = |cap| Layout::array::<u64>(cap).unwrap();
+        unsafe {
+            let ptr = core::ptr::NonNull::new_unchecked(v.as_mut_ptr().cast::<u8>());
+            assert_eq!(ptr.as_ptr() as usize % 8, 0);
+            Alloq::prefix(ptr).write(0xdead);
+            Alloq::suffix(ptr, layout(1)).write(0xbeef);
+        }
+        v.extend(0..64);
+        unsafe {
+            let ptr = core::ptr::NonNull::new_unchecked(v.as_mut_ptr().cast::<u8>());
+            assert_eq!(Alloq::prefix(ptr).read(), 0xdead);
+            assert_eq!(Alloq::suffix(ptr, layout(v.capacity())).read(), 0xbeef);
+        }
+        assert_eq!(v[0], 7);
+        assert_eq!(v[1..].iter().sum::<u64>(), (0..64).sum());
+    }
+
+    #[test]
+    #[cfg(feature = "list")]
+    fn affix_many_layouts() {
+        use super::Affix;
+        use crate::Alloqator;
+        use alloc::{vec, vec::Vec};
+        use core::alloc::{Allocator, Layout};
+
+        type Inner = crate::list::first::Alloq;
+        type Alloq = Affix<Inner, u32, u16>;
+        // More than a fixed buffer of outer layouts would hold, with zero-sized ones between.
+        let layouts: Vec<_> = (0..100)
+            .map(|i| match i % 10 {
+                0 => Layout::new::<()>(),
+                _ => Layout::from_size_align(i, 1 << (i % 4)).unwrap(),
+            })
+            .collect();
+        let outer: Vec<_> = layouts
+            .iter()
+            .map(|&layout| match layout.size() {
+                0 => layout,
+                _ => Alloq::outer_layout(layout).unwrap().0,
+            })
+            .collect();
+        let size = Alloq::required_heap_size(&layouts).unwrap();
+        assert_eq!(Some(size), Inner::required_heap_size(&outer));
+
+        let mut heap = vec![0u64; size.div_ceil(8)];
+        let heap = heap.as_mut_ptr_range();
+        let alloqer = Alloq::new(heap.start.cast()..heap.start.cast::<u8>().wrapping_add(size));
+        for &layout in &layouts {
+            alloqer.allocate(layout).unwrap();
+        }
+        // Affixes can't be nested in a plan.
+        assert_eq!(Affix::<Alloq, u8>::required_heap_size(&layouts), None);
+    }
+
+    #[test]
+    #[cfg(feature = "system")]
+    fn budget_limit() {
+        use super::{Budget, Watermark};
+        use crate::Alloqator;
+        use core::{
+            alloc::{Allocator, Layout},
+            ptr,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        static SOFT: AtomicUsize = AtomicUsize::new(0);
+        static HARD: AtomicUsize = AtomicUsize::new(0);
+        fn watermark(watermark: Watermark, used: usize) {
+            match watermark {
+                Watermark::Soft => SOFT.store(used, Ordering::Relaxed),
+                Watermark::Hard => HARD.store(used, Ordering::Relaxed),
+            }
+        }
+        let alloqer = Budget::with(
+            crate::system::Alloq::new(ptr::null_mut()..ptr::null_mut()),
+            256,
+        )
+        .soft_limit(128)
+        .on_watermark(watermark);
+        let layout = Layout::new::<[u8; 100]>();
+        let a = alloqer.allocate(layout).unwrap();
+        assert_eq!(SOFT.load(Ordering::Relaxed), 0);
+        let b = alloqer.allocate(layout).unwrap();
+        assert_eq!(SOFT.load(Ordering::Relaxed), 200);
+        assert!(alloqer.allocate(layout).is_err());
+        assert_eq!(HARD.load(Ordering::Relaxed), 300);
+        assert_eq!((alloqer.used(), alloqer.peak()), (200, 200));
+        unsafe { alloqer.deallocate(a.cast(), layout) };
+        let c = alloqer.allocate(Layout::new::<[u8; 156]>()).unwrap();
+        assert_eq!((alloqer.used(), alloqer.peak()), (256, 256));
+        unsafe {
+            alloqer.deallocate(b.cast(), layout);
+            alloqer.deallocate(c.cast(), Layout::new::<[u8; 156]>());
+        }
+        assert_eq!((alloqer.used(), alloqer.peak()), (0, 256));
+        alloqer.reset_peak();
+        assert_eq!(alloqer.peak(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "system")]
+    fn budget_without_heap() {
+        use super::Budget;
+        use crate::Alloqator;
+        use core::{alloc::Layout, ptr};
+
+        let alloqer = Budget::with(
+            crate::system::Alloq::new(ptr::null_mut()..ptr::null_mut()),
+            256,
+        );
+        assert!(alloqer.heap_range().is_empty());
+        let layout = Layout::new::<[u8; 200]>();
+        let ptr = alloqer.alloq(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(alloqer.used(), 200);
+        unsafe {
+            alloqer.dealloq(ptr, layout);
+            alloqer.hard_reset();
+        }
+        assert_eq!(alloqer.used(), 0);
+        let ptr = alloqer.alloq(layout);
+        assert!(!ptr.is_null());
+        unsafe { alloqer.dealloq(ptr, layout) };
+    }
+
+    #[test]
+    #[cfg(feature = "system")]
+    fn budget_grow() {
+        use super::Budget;
+        use crate::Alloqator;
+        use alloc::vec::Vec;
+        use core::ptr;
+
+        let alloqer = Budget::with(
+            crate::system::Alloq::new(ptr::null_mut()..ptr::null_mut()),
+            1024,
+        );
+        let mut v: Vec<u8, _> = Vec::new_in(&alloqer);
+        v.extend(0..=255);
+        assert_eq!(alloqer.used(), v.capacity());
+        v.truncate(16);
+        v.shrink_to_fit();
+        assert_eq!(alloqer.used(), 16);
+        assert!(v.try_reserve_exact(2048).is_err());
+        assert_eq!(alloqer.used(), 16);
+        drop(v);
+        assert_eq!(alloqer.used(), 0);
+    }
+
+    #[test]
+    #[cfg(all(feature = "list", feature = "bump", feature = "pool"))]
+    fn sub_heaps() {
+        use super::SubHeap;
+        use crate::Alloqator;
+        use alloc::{boxed::Box, vec::Vec};
+        use core::alloc::Layout;
+
+        let mut heap = [0u8; 1024 * 16];
+        let parent = crate::list::first::Alloq::new(heap.as_mut_ptr_range());
+        let layout = Layout::from_size_align(1024 * 4, 16).unwrap();
+        let first = {
+            let arena = SubHeap::<_, crate::bump::Alloq>::new(&parent, layout).unwrap();
+            let pool = SubHeap::<_, crate::pool::Alloq>::with_size(&parent, 1024 * 4).unwrap();
+            let (block, _) = arena.block();
+            assert!(parent.heap_range().contains(&block.as_ptr()));
+            assert_eq!(arena.heap_start(), block.as_ptr());
+            let mut v = Vec::new_in(&arena);
+            v.extend(0..64u32);
+            let b = Box::new_in(42u64, &pool);
+            assert!(arena.heap_range().contains(&v.as_mut_ptr().cast()));
+            assert!(pool
+                .heap_range()
+                .contains(&(&*b as *const u64).cast_mut().cast()));
+            assert_eq!(v.iter().sum::<u32>() as u64 + *b, (0..64).sum::<u64>() + 42);
+            block
+        };
+        // Both blocks were given back, so the same block is found again.
+        let again = SubHeap::<_, crate::bump::Alloq>::new(&parent, layout).unwrap();
+        assert_eq!(again.block().0, first);
+    }
+}
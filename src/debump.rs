@@ -4,9 +4,11 @@ use core::{
     ops::Range,
     ptr::{null_mut, NonNull},
 };
-use spin::Mutex;
 
-use crate::Alloqator;
+use crate::{
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AlloqMetaData {
@@ -52,13 +54,47 @@ impl AlloqMetaData {
 ///     (A, 0x1) -> (B, 0x2) -> (C, 0x0) -> (D, 0x0)
 ///     (A, 0x1) -> (B, 0x2) -> (C, 0x0)
 ///     (A, 0x1) -> (B, 0x2)
-pub struct Alloq {
+pub struct Alloq<L: RawLock = Spin> {
     pub heap_start: *mut u8,
     pub heap_end: *mut u8,
-    pub last_meta: Mutex<*const AlloqMetaData>,
+    pub last_meta: Mutex<L, *const AlloqMetaData>,
 }
 
-impl Alloq {
+impl<L: RawLock> Alloq<L> {
+    /// `Alloqator::required_heap_size`, but `const`.
+    pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, Affixes::NONE)
+    }
+
+    /// `Alloqator::required_affixed_heap_size`, but `const`. Counts `pad_alloc`'s metadata,
+    /// which must leave a byte after it, and the metadata after each block.
+    pub const fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        const META: usize = mem::size_of::<AlloqMetaData>();
+        const META_ALIGN: usize = mem::align_of::<AlloqMetaData>();
+        let mut last_meta = 0usize;
+        let mut end = META + 1;
+        let mut i = 0;
+        while i < layouts.len() {
+            let layout = match affixes.plan(layouts[i]) {
+                Some(layout) => layout,
+                None => return None,
+            };
+            i += 1;
+            if layout.size() == 0 {
+                continue;
+            }
+            let obj_end = match crate::align_up(last_meta + META, layout.align())
+                .checked_add(layout.size())
+            {
+                Some(obj_end) if obj_end < usize::MAX - META - META_ALIGN => obj_end,
+                _ => return None,
+            };
+            last_meta = crate::align_up(obj_end, META_ALIGN);
+            end = last_meta + META;
+        }
+        Some(end)
+    }
+
     pub fn pad_alloc(heap_range: Range<*mut u8>) -> *const AlloqMetaData {
         let layout = Layout::new::<()>();
         let aligned = crate::align_up(heap_range.start as usize, layout.align()) as *mut u8;
@@ -74,25 +110,35 @@ impl Alloq {
     }
 }
 
-unsafe impl Allocator for Alloq {
+unsafe impl<L: RawLock> Allocator for Alloq<L> {
     /// Similar to `crate::bump::Bump::alloc` (O(1) so), but also allocates a `AlloqMetaData` in the top of
     /// stack, containing where is the block, where is the last `AlloqMetaData` allocated and if
-    /// it's being used
+    /// it's being used. The block goes until that metadata, see `Alloqator::usable_size`.
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let mut last_meta = self.last_meta.lock();
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
+        }
+        let mut last_meta = self.last_meta.acquire()?;
         let ptr = unsafe {
             let end = last_meta.add(1);
-            let obj_addr = crate::align_up(end as usize, layout.align()) as *mut u8;
+            let obj_addr = crate::align_up(end as usize, layout.align());
+            // The metadata goes right after the block, so both must fit.
+            let meta_end = obj_addr
+                .checked_add(layout.size())
+                .filter(|&obj_end| obj_end <= self.heap_end as usize)
+                .map(|obj_end| {
+                    crate::align_up(obj_end, mem::align_of::<AlloqMetaData>())
+                        + mem::size_of::<AlloqMetaData>()
+                });
+            if !matches!(meta_end, Some(meta_end) if meta_end <= self.heap_end as usize) {
+                return Err(AllocError);
+            }
+            let obj_addr = self.heap_start.with_addr(obj_addr);
             *last_meta = AlloqMetaData::new(obj_addr, *last_meta).write_meta(layout);
-            debug_assert!(
-                self.heap_range().contains(&(last_meta.add(1) as *mut u8)),
-                "can't allocate a new layout. heap range {:?} ({} bytes)",
-                self.heap_range(),
-                self.heap_end.offset_from(self.heap_start) as usize
-            );
             (**last_meta).start.unwrap().as_ptr()
         };
-        let slice = unsafe { core::slice::from_raw_parts_mut(ptr, layout.size()) };
+        let usable = *last_meta as usize - ptr as usize;
+        let slice = unsafe { core::slice::from_raw_parts_mut(ptr, usable) };
         NonNull::new(slice).ok_or(AllocError)
     }
 
@@ -100,10 +146,13 @@ unsafe impl Allocator for Alloq {
     /// stack pointer to `last_meta`) all the last areas marked as unused. In the worst case, can
     /// be O(n), where `n` is the number of allocations.
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
         let mut last_meta = self.last_meta.lock();
         let meta = AlloqMetaData::from_alloc_ptr(ptr.as_ptr(), layout);
         meta.start = None;
-        if meta as *const AlloqMetaData == *last_meta {
+        if core::ptr::eq(meta, *last_meta) {
             while (**last_meta).start.is_none() {
                 *last_meta = (**last_meta).last_meta;
             }
@@ -111,9 +160,17 @@ unsafe impl Allocator for Alloq {
     }
 }
 
-impl Alloqator for Alloq {
+impl<L: RawLock> Alloqator for Alloq<L> {
     type Metadata = AlloqMetaData;
 
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, affixes)
+    }
+
     fn new(heap_range: Range<*mut u8>) -> Self {
         Self {
             heap_start: heap_range.start,
@@ -132,6 +189,15 @@ impl Alloqator for Alloq {
         self.heap_end
     }
 
+    /// Until the block's metadata, which is aligned after it.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if layout.size() == 0 {
+            return 0;
+        }
+        let meta = AlloqMetaData::from_alloc_ptr(ptr.as_ptr(), layout);
+        meta as *mut AlloqMetaData as usize - ptr.as_ptr() as usize
+    }
+
     #[inline(always)]
     unsafe fn reset(&self) {
         let mut lock = self.last_meta.lock();
@@ -139,11 +205,13 @@ impl Alloqator for Alloq {
     }
 }
 
-crate::impl_allocator!(Alloq);
+crate::impl_allocator!(Alloq<L: RawLock>);
 
 #[cfg(test)]
 pub mod tests {
-    use super::Alloq;
+    type Alloq = super::Alloq;
+
+    const PLANS_HEAP: bool = true;
 
     include!("test.template.rs");
 }
@@ -0,0 +1,155 @@
+use core::{fmt, ops::Range};
+
+use crate::Alloqator;
+
+/// What a region of the heap holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Free,
+    Used,
+    /// The allocator's own data, like list's nodes or pool's `RawChunk`s, and the padding that
+    /// nobody can use.
+    Metadata,
+}
+
+impl Region {
+    /// Its value in the heap map, which `bench.gp` uses as the color.
+    pub const fn level(self) -> u8 {
+        match self {
+            Self::Free => 0,
+            Self::Used => 1,
+            Self::Metadata => 2,
+        }
+    }
+}
+
+/// Free blocks are counted in power-of-two buckets.
+pub const BUCKETS: usize = usize::BITS as usize;
+
+/// How the heap is split, to compare allocators under the same workload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub heap_size: usize,
+    /// Allocated bytes, with their slack.
+    pub used: usize,
+    pub free: usize,
+    pub metadata: usize,
+    pub free_blocks: usize,
+    pub largest_free: usize,
+    /// `histogram[i]` counts the free blocks from `2^i` to `2^(i + 1) - 1` bytes.
+    pub histogram: [usize; BUCKETS],
+}
+
+impl Fragmentation {
+    pub const fn new(heap_size: usize) -> Self {
+        Self {
+            heap_size,
+            used: 0,
+            free: 0,
+            metadata: 0,
+            free_blocks: 0,
+            largest_free: 0,
+            histogram: [0; BUCKETS],
+        }
+    }
+
+    pub fn add(&mut self, region: Region, size: usize) {
+        match region {
+            Region::Used => self.used += size,
+            Region::Metadata => self.metadata += size,
+            Region::Free if size != 0 => {
+                self.free += size;
+                self.free_blocks += 1;
+                self.largest_free = self.largest_free.max(size);
+                self.histogram[size.ilog2() as usize] += 1;
+            }
+            Region::Free => {}
+        }
+    }
+
+    /// External fragmentation, `1 - largest_free / free`: 0 when the free bytes are a single
+    /// block (or there are none), near 1 when they are scattered in small ones.
+    pub fn external(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free as f64 / self.free as f64
+        }
+    }
+
+    /// Percentage of the heap taken by metadata.
+    pub fn metadata_overhead(&self) -> f64 {
+        if self.heap_size == 0 {
+            0.0
+        } else {
+            self.metadata as f64 * 100.0 / self.heap_size as f64
+        }
+    }
+}
+
+impl fmt::Display for Fragmentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "heap: {} bytes, used: {}, free: {} in {} blocks (largest: {}), metadata: {}",
+            self.heap_size,
+            self.used,
+            self.free,
+            self.free_blocks,
+            self.largest_free,
+            self.metadata
+        )?;
+        writeln!(
+            f,
+            "external fragmentation: {:.3}, metadata overhead: {:.2}%",
+            self.external(),
+            self.metadata_overhead()
+        )?;
+        for (bucket, &count) in self.histogram.iter().enumerate() {
+            if count != 0 {
+                writeln!(
+                    f,
+                    "free blocks of {} bytes and up: {count}",
+                    1usize << bucket
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Allocators that can go over their whole heap, to measure its fragmentation.
+pub trait HeapWalk: Alloqator {
+    /// Calls `f` with every region, in order from `heap_start` to `heap_end`. Adjacent free
+    /// regions are a single one. It holds the allocator's lock, so `f` can't allocate from it.
+    fn walk(&self, f: &mut dyn FnMut(Range<*mut u8>, Region));
+
+    fn fragmentation(&self) -> Fragmentation {
+        let mut report = Fragmentation::new(self.heap_end() as usize - self.heap_start() as usize);
+        self.walk(&mut |range, region| {
+            report.add(region, range.end as usize - range.start as usize)
+        });
+        report
+    }
+
+    /// Writes the heap map as CSV, a line `offset, size, level` (see `Region::level`) per region,
+    /// with offsets from `heap_start`. `bench.gp` plots the `*.map` files written by the
+    /// benchmark. Like `walk`, `w` can't allocate from this allocator.
+    fn write_heap_map(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        let start = self.heap_start() as usize;
+        writeln!(w, "offset, size, level")?;
+        let mut result = Ok(());
+        self.walk(&mut |range, region| {
+            if result.is_ok() {
+                result = writeln!(
+                    w,
+                    "{}, {}, {}",
+                    range.start as usize - start,
+                    range.end as usize - range.start as usize,
+                    region.level()
+                );
+            }
+        });
+        result
+    }
+}
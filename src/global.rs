@@ -0,0 +1,122 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+    ptr::{null_mut, NonNull},
+};
+
+use crate::Alloqator;
+
+/// Implements `GlobalAlloc` for any `Alloqator`, so allocators from other crates can be a
+/// `#[global_allocator]` too. `realloc` goes to `grow`/`shrink` and `alloc_zeroed` to
+/// `allocate_zeroed`, so allocators that can resize in place do it, and failures return null.
+#[repr(transparent)]
+#[derive(Debug, Default)]
+pub struct AsGlobal<A: Alloqator>(pub A);
+
+impl<A: Alloqator> AsGlobal<A> {
+    pub const fn new(alloqer: A) -> Self {
+        Self(alloqer)
+    }
+
+    /// Views a borrowed allocator as `AsGlobal`.
+    #[inline(always)]
+    pub fn from_ref(alloqer: &A) -> &Self {
+        // SAFE: `Self` is `repr(transparent)`.
+        unsafe { &*(alloqer as *const A).cast() }
+    }
+
+    pub fn into_inner(self) -> A {
+        self.0
+    }
+}
+
+impl<A: Alloqator> Deref for AsGlobal<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.0
+    }
+}
+
+unsafe impl<A: Alloqator> GlobalAlloc for AsGlobal<A> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .allocate(layout)
+            .map_or(null_mut(), |block| block.as_ptr().cast())
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .allocate_zeroed(layout)
+            .map_or(null_mut(), |block| block.as_ptr().cast())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let ptr = NonNull::new_unchecked(ptr);
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let block = if new_size >= layout.size() {
+            self.0.grow(ptr, layout, new_layout)
+        } else {
+            self.0.shrink(ptr, layout, new_layout)
+        };
+        block.map_or(null_mut(), |block| block.as_ptr().cast())
+    }
+}
+
+#[cfg(all(test, feature = "bump", feature = "list"))]
+pub mod tests {
+    use super::AsGlobal;
+    use crate::Alloqator;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn realloc() {
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = AsGlobal::new(crate::list::first::Alloq::new(heap.as_mut_ptr_range()));
+        let layout = Layout::array::<u32>(4).unwrap();
+        unsafe {
+            let ptr = alloqer.alloc(layout).cast::<u32>();
+            (0..4).for_each(|i| ptr.add(i).write(i as u32));
+            let ptr = alloqer.realloc(ptr.cast(), layout, 64 * 4).cast::<u32>();
+            assert!((0..4).all(|i| ptr.add(i).read() == i as u32));
+            let layout = Layout::array::<u32>(64).unwrap();
+            let ptr = alloqer.realloc(ptr.cast(), layout, 2 * 4).cast::<u32>();
+            assert!((0..2).all(|i| ptr.add(i).read() == i as u32));
+            alloqer.dealloc(ptr.cast(), Layout::array::<u32>(2).unwrap());
+        }
+    }
+
+    #[test]
+    fn alloc_zeroed() {
+        let mut heap = [0xffu8; 1024];
+        let alloqer = AsGlobal::new(<crate::bump::Alloq>::new(heap.as_mut_ptr_range()));
+        let layout = Layout::new::<[u8; 64]>();
+        unsafe {
+            let ptr = alloqer.alloc_zeroed(layout);
+            assert!(core::slice::from_raw_parts(ptr, 64).iter().all(|&b| b == 0));
+            alloqer.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let mut heap = [0u8; 64];
+        let alloqer = <crate::bump::Alloq>::new(heap.as_mut_ptr_range());
+        let global = AsGlobal::from_ref(&alloqer);
+        unsafe {
+            let ptr = global.alloc(Layout::new::<[u8; 32]>());
+            assert!(!ptr.is_null());
+            assert!(global.alloc(Layout::new::<[u8; 64]>()).is_null());
+            assert!(global
+                .realloc(ptr, Layout::new::<[u8; 32]>(), 128)
+                .is_null());
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    fmt, hint,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::{Deref, Range},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{Alloqator, AsGlobal};
+
+/// An allocator built on first use, since it must point to its final heap.
+struct Lazy<A: Alloqator> {
+    alloqer: UnsafeCell<MaybeUninit<A>>,
+    /// The heap's start when `alloqer` was built, null before or `BUILDING` while building.
+    built_for: AtomicPtr<u8>,
+}
+
+/// Marks a `Lazy` being built by another thread.
+const BUILDING: *mut u8 = ptr::dangling_mut();
+
+/// Unmarks a `Lazy` being built if `A::new` unwinds, so the next call builds it again instead of
+/// waiting forever.
+struct Building<'a>(&'a AtomicPtr<u8>);
+
+impl Drop for Building<'_> {
+    fn drop(&mut self) {
+        // The old allocator was already dropped, so there's none.
+        self.0.store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+impl<A: Alloqator> Lazy<A> {
+    const fn new() -> Self {
+        Self {
+            alloqer: UnsafeCell::new(MaybeUninit::uninit()),
+            built_for: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns the allocator, (re)building it if it wasn't built over `heap_range`.
+    fn get(&self, heap_range: Range<*mut u8>) -> &A {
+        loop {
+            let built_for = self.built_for.load(Ordering::Acquire);
+            if built_for == heap_range.start {
+                return unsafe { (*self.alloqer.get()).assume_init_ref() };
+            }
+            if built_for == BUILDING {
+                hint::spin_loop();
+                continue;
+            }
+            if self
+                .built_for
+                .compare_exchange_weak(built_for, BUILDING, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                let building = Building(&self.built_for);
+                unsafe {
+                    let alloqer = &mut *self.alloqer.get();
+                    if !built_for.is_null() {
+                        alloqer.assume_init_drop();
+                    }
+                    alloqer.write(A::new(heap_range.clone()));
+                }
+                mem::forget(building);
+                self.built_for.store(heap_range.start, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl<A: Alloqator> Drop for Lazy<A> {
+    fn drop(&mut self) {
+        if !self.built_for.get_mut().is_null() {
+            unsafe { self.alloqer.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// `N` bytes aligned to `M` and to `usize`, like `from_range` aligns heaps.
+#[repr(C)]
+struct Buffer<M, const N: usize> {
+    _metadata: [M; 0],
+    _usize: [usize; 0],
+    bytes: [MaybeUninit<u8>; N],
+}
+
+/// An allocator bundled with its `N` bytes buffer, so it can't outlive its memory. As the
+/// allocator points to the buffer, it's built on first use (`new` is a `const fn`, usable in a
+/// `static`), and rebuilt if the heap was moved since. A heap can't move while any allocation
+/// borrows it, so nothing is lost.
+/// The buffer is aligned to `A::Metadata` and to `usize`, so `A::required_heap_size` fits
+/// layouts aligned up to that, wherever the heap is placed.
+pub struct Heap<A: Alloqator, const N: usize> {
+    buffer: UnsafeCell<Buffer<A::Metadata, N>>,
+    lazy: Lazy<A>,
+}
+
+// SAFE: the allocator is only written while building, which is exclusive.
+unsafe impl<A: Alloqator + Send, const N: usize> Send for Heap<A, N> {}
+unsafe impl<A: Alloqator + Sync, const N: usize> Sync for Heap<A, N> {}
+
+impl<A: Alloqator, const N: usize> Heap<A, N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: UnsafeCell::new(Buffer {
+                _metadata: [],
+                _usize: [],
+                bytes: [MaybeUninit::uninit(); N],
+            }),
+            lazy: Lazy::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn heap_range(&self) -> Range<*mut u8> {
+        let start = self.buffer.get().cast::<u8>();
+        start..start.wrapping_add(N)
+    }
+
+    /// Returns the allocator, building it if needed.
+    #[inline(always)]
+    pub fn alloqer(&self) -> &A {
+        self.lazy.get(self.heap_range())
+    }
+}
+
+impl<A: Alloqator, const N: usize> Default for Heap<A, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Alloqator, const N: usize> fmt::Debug for Heap<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heap")
+            .field("heap_range", &self.heap_range())
+            .finish()
+    }
+}
+
+impl<A: Alloqator, const N: usize> Deref for Heap<A, N> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.alloqer()
+    }
+}
+
+unsafe impl<A: Alloqator, const N: usize> Allocator for Heap<A, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.alloqer().deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().shrink(ptr, old_layout, new_layout)
+    }
+}
+
+unsafe impl<A: Alloqator, const N: usize> GlobalAlloc for Heap<A, N> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).alloc(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        AsGlobal::from_ref(self.alloqer()).dealloc(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).alloc_zeroed(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).realloc(ptr, layout, new_size)
+    }
+}
+
+/// An allocator over a fixed region (a `static` buffer or the linker's heap), built on the
+/// first allocation. As `new` is a `const fn`, it can be the `#[global_allocator]`:
+/// ```ignore
+/// static mut HEAP: [MaybeUninit<u8>; 1 << 20] = [MaybeUninit::uninit(); 1 << 20];
+///
+/// #[global_allocator]
+/// static GLOBAL: Global<list::first::Alloq> = unsafe { Global::from_static(&raw mut HEAP) };
+/// ```
+pub struct Global<A: Alloqator> {
+    heap_range: Range<*mut u8>,
+    lazy: Lazy<A>,
+}
+
+// SAFE: the allocator is only written while building, which is exclusive.
+unsafe impl<A: Alloqator + Send> Send for Global<A> {}
+unsafe impl<A: Alloqator + Sync> Sync for Global<A> {}
+
+impl<A: Alloqator> Global<A> {
+    /// # Safety
+    /// `heap_range` must be valid for the whole program and not used by anything else.
+    pub const unsafe fn new(heap_range: Range<*mut u8>) -> Self {
+        Self {
+            heap_range,
+            lazy: Lazy::new(),
+        }
+    }
+
+    /// # Safety
+    /// Same as `new`.
+    pub const unsafe fn from_static<const N: usize>(buffer: *mut [MaybeUninit<u8>; N]) -> Self {
+        let start = buffer.cast::<u8>();
+        Self::new(start..start.wrapping_add(N))
+    }
+
+    #[inline(always)]
+    pub fn heap_range(&self) -> Range<*mut u8> {
+        self.heap_range.clone()
+    }
+
+    /// Returns the allocator, building it if needed.
+    #[inline(always)]
+    pub fn alloqer(&self) -> &A {
+        self.lazy.get(self.heap_range())
+    }
+}
+
+impl<A: Alloqator> fmt::Debug for Global<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Global")
+            .field("heap_range", &self.heap_range)
+            .finish()
+    }
+}
+
+impl<A: Alloqator> Deref for Global<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        self.alloqer()
+    }
+}
+
+unsafe impl<A: Alloqator> Allocator for Global<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.alloqer().deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer().shrink(ptr, old_layout, new_layout)
+    }
+}
+
+unsafe impl<A: Alloqator> GlobalAlloc for Global<A> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).alloc(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        AsGlobal::from_ref(self.alloqer()).dealloc(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).alloc_zeroed(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        AsGlobal::from_ref(self.alloqer()).realloc(ptr, layout, new_size)
+    }
+}
+
+/// Shrinks `heap_range` to the biggest range inside it aligned to `align` on both ends.
+pub fn align_range(heap_range: Range<*mut u8>, align: usize) -> Range<*mut u8> {
+    let start = crate::align_up(heap_range.start as usize, align);
+    let end = crate::align_down(heap_range.end as usize, align).max(start);
+    heap_range.start.with_addr(start)..heap_range.start.with_addr(end)
+}
+
+/// Builds `A` over `heap_range`, aligned to its metadata and to `usize`.
+/// # Safety
+/// `heap_range` must be valid, writable and not used by anything else while `A` is used.
+pub unsafe fn from_range<A: Alloqator>(heap_range: Range<*mut u8>) -> A {
+    let align = mem::align_of::<A::Metadata>().max(mem::align_of::<usize>());
+    A::new(align_range(heap_range, align))
+}
+
+/// Builds `A` over the region between two linker symbols, see `heap_from_symbols!`.
+/// # Safety
+/// Same as `from_range`.
+pub unsafe fn from_symbols<A: Alloqator>(start: *mut u8, end: *mut u8) -> A {
+    from_range(start..end)
+}
+
+/// Builds `A` over a `'static` buffer, e.g. from `static_cell` or `Box::leak`.
+pub fn from_static<A: Alloqator>(buffer: &'static mut [MaybeUninit<u8>]) -> A {
+    let range = buffer.as_mut_ptr_range();
+    unsafe { from_range(range.start.cast()..range.end.cast()) }
+}
+
+/// Builds an `Alloqator` over the heap given by the linker, between `_heap_start` and
+/// `_heap_end` (or the given symbols):
+/// ```ignore
+/// let alloqer: list::first::Alloq = alloq::heap_from_symbols!(list::first::Alloq);
+/// let alloqer = alloq::heap_from_symbols!(bump::Alloq, __sheap, __eheap);
+/// ```
+/// # Safety
+/// The symbols must delimit a valid heap, not used by anything else. For a
+/// `#[global_allocator]`, use `Global::new(&raw mut _heap_start..&raw mut _heap_end)` instead.
+#[macro_export]
+macro_rules! heap_from_symbols {
+    ($typ:ty) => {
+        $crate::heap_from_symbols!($typ, _heap_start, _heap_end)
+    };
+    ($typ:ty, $start:ident, $end:ident) => {{
+        extern "C" {
+            static mut $start: u8;
+            static mut $end: u8;
+        }
+        unsafe { $crate::heap::from_symbols::<$typ>(&raw mut $start, &raw mut $end) }
+    }};
+}
+
+/// An allocator over a borrowed buffer, so it can't outlive it.
+pub struct BorrowedHeap<'a, A: Alloqator> {
+    pub alloqer: A,
+    _buffer: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a, A: Alloqator> BorrowedHeap<'a, A> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            alloqer: A::new(buffer.as_mut_ptr_range()),
+            _buffer: PhantomData,
+        }
+    }
+
+    pub fn from_uninit(buffer: &'a mut [MaybeUninit<u8>]) -> Self {
+        let range = buffer.as_mut_ptr_range();
+        Self {
+            alloqer: A::new(range.start.cast()..range.end.cast()),
+            _buffer: PhantomData,
+        }
+    }
+}
+
+impl<A: Alloqator> Deref for BorrowedHeap<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.alloqer
+    }
+}
+
+unsafe impl<A: Alloqator> Allocator for BorrowedHeap<'_, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.alloqer.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer.grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloqer.shrink(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(all(test, feature = "list"))]
+pub mod tests {
+    extern crate alloc;
+    extern crate std;
+
+    use super::{align_range, BorrowedHeap, Global, Heap};
+    use crate::{list::first::Alloq, Alloqator};
+    use alloc::{boxed::Box, vec::Vec};
+    use core::mem::MaybeUninit;
+    use std::thread;
+
+    static HEAP: Heap<Alloq, { 1024 * 16 }> = Heap::new();
+
+    fn with_global<A: Alloqator + Sync>(global: &Global<A>) {
+        thread::scope(|s| {
+            for t in 0..4 {
+                s.spawn(move || {
+                    let v: Vec<_, _> = (0..16).map(|i| Box::new_in(t * 16 + i, global)).collect();
+                    assert!(v.iter().enumerate().all(|(i, b)| **b == t * 16 + i));
+                    assert!(v.iter().all(|b| global
+                        .heap_range()
+                        .contains(&(&**b as *const usize).cast_mut().cast())));
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn globals() {
+        const N: usize = 1024 * 16;
+        static mut LIST: [MaybeUninit<u8>; N] = [MaybeUninit::uninit(); N];
+        static LIST_GLOBAL: Global<Alloq> = unsafe { Global::from_static(&raw mut LIST) };
+        with_global(&LIST_GLOBAL);
+        #[cfg(feature = "bump")]
+        {
+            static mut BUMP: [MaybeUninit<u8>; N] = [MaybeUninit::uninit(); N];
+            static BUMP_GLOBAL: Global<crate::bump::Alloq> =
+                unsafe { Global::from_static(&raw mut BUMP) };
+            with_global(&BUMP_GLOBAL);
+        }
+        #[cfg(feature = "debump")]
+        {
+            static mut DEBUMP: [MaybeUninit<u8>; N] = [MaybeUninit::uninit(); N];
+            static DEBUMP_GLOBAL: Global<crate::debump::Alloq> =
+                unsafe { Global::from_static(&raw mut DEBUMP) };
+            with_global(&DEBUMP_GLOBAL);
+        }
+        #[cfg(feature = "statiq")]
+        {
+            static mut STATIQ: [MaybeUninit<u8>; N] = [MaybeUninit::uninit(); N];
+            static STATIQ_GLOBAL: Global<crate::statiq::Alloq> =
+                unsafe { Global::from_static(&raw mut STATIQ) };
+            with_global(&STATIQ_GLOBAL);
+        }
+        #[cfg(feature = "pool")]
+        {
+            static mut POOL: [MaybeUninit<u8>; N] = [MaybeUninit::uninit(); N];
+            static POOL_GLOBAL: Global<crate::pool::Alloq> =
+                unsafe { Global::from_static(&raw mut POOL) };
+            with_global(&POOL_GLOBAL);
+        }
+    }
+
+    #[test]
+    fn static_heap() {
+        thread::scope(|s| {
+            for t in 0..4 {
+                s.spawn(move || {
+                    let v: Vec<_, _> = (0..64).map(|i| Box::new_in(t * 64 + i, &HEAP)).collect();
+                    assert!(v.iter().enumerate().all(|(i, b)| **b == t * 64 + i));
+                });
+            }
+        });
+        assert_eq!(HEAP.heap_start(), HEAP.heap_range().start);
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn aligned_heap() {
+        // Its metadata is aligned to `u128`, more than the rest of the `Heap`.
+        type Affixed = crate::compose::Affix<Alloq, u128>;
+        let heaps = [Heap::<Affixed, 264>::new(), Heap::new()];
+        let align = core::mem::align_of::<<Affixed as Alloqator>::Metadata>();
+        for heap in &heaps {
+            assert!((heap.heap_range().start as usize).is_multiple_of(align));
+            assert!(!heap.alloq(core::alloc::Layout::new::<u128>()).is_null());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pool")]
+    fn panicking_build() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        // Too small for a chunk, so `pool::Alloq::new` panics every time.
+        let heap = Heap::<crate::pool::Alloq, 16>::new();
+        for _ in 0..2 {
+            assert!(catch_unwind(AssertUnwindSafe(|| heap.alloqer().heap_start())).is_err());
+        }
+    }
+
+    #[test]
+    fn moved_heap() {
+        let heap = Box::new(Heap::<Alloq, 1024>::new());
+        {
+            let mut v = Vec::new_in(&*heap);
+            v.extend(0..16);
+            assert!(heap.heap_range().contains(&v.as_mut_ptr().cast()));
+        }
+        // Moved out of the box: the allocator is rebuilt over the new buffer.
+        let heap = *heap;
+        let mut v = Vec::new_in(&heap);
+        v.extend(0..16);
+        assert!(heap.heap_range().contains(&v.as_mut_ptr().cast()));
+        assert_eq!(heap.heap_start(), heap.heap_range().start);
+        assert_eq!(v.iter().sum::<i32>(), (0..16).sum());
+    }
+
+    #[test]
+    fn borrowed_heap() {
+        let mut buffer = [0u8; 1024];
+        let heap = BorrowedHeap::<Alloq>::new(&mut buffer);
+        let mut v = Vec::new_in(&heap);
+        v.extend(0..16);
+        assert_eq!(v.iter().sum::<i32>(), (0..16).sum());
+        drop(v);
+        let mut uninit = [MaybeUninit::uninit(); 1024];
+        let heap = BorrowedHeap::<Alloq>::from_uninit(&mut uninit);
+        let b = Box::new_in(42u64, &heap);
+        assert_eq!(*b, 42);
+    }
+
+    #[test]
+    fn aligned_ranges() {
+        let base = core::ptr::null_mut::<u8>();
+        let range = align_range(base.wrapping_add(3)..base.wrapping_add(61), 8);
+        assert_eq!(range, base.wrapping_add(8)..base.wrapping_add(56));
+        let range = align_range(base.wrapping_add(3)..base.wrapping_add(5), 8);
+        assert!(range.is_empty());
+    }
+
+    #[test]
+    fn from_static() {
+        let buffer = Box::leak(Box::new([MaybeUninit::uninit(); 1024]));
+        let alloqer: Alloq = super::from_static(&mut buffer[1..]);
+        assert_eq!(
+            alloqer.heap_start() as usize % core::mem::align_of::<usize>(),
+            0
+        );
+        let b = Box::new_in(42u64, &alloqer);
+        assert_eq!(*b, 42);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[no_mangle]
+    static mut _alloq_test_heap_start: [usize; 4096 / size_of::<usize>()] = [0; _];
+
+    #[cfg(target_os = "linux")]
+    core::arch::global_asm!(
+        ".globl _alloq_test_heap_end",
+        ".set _alloq_test_heap_end, _alloq_test_heap_start + 4096"
+    );
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn from_symbols() {
+        let alloqer =
+            crate::heap_from_symbols!(Alloq, _alloq_test_heap_start, _alloq_test_heap_end);
+        let start = (&raw mut _alloq_test_heap_start).cast::<u8>();
+        assert_eq!(alloqer.heap_range(), start..start.wrapping_add(4096));
+        let mut v = Vec::new_in(&alloqer);
+        v.extend(0..64);
+        assert_eq!(v.iter().sum::<i32>(), (0..64).sum());
+    }
+}
@@ -8,12 +8,19 @@
 //     clippy::cargo
 // )]
 use core::{
-    alloc::{Allocator, Layout},
+    alloc::{AllocError, Allocator, Layout},
     mem,
     ops::Range,
     ptr::NonNull,
 };
 
+pub mod fragmentation;
+pub mod global;
+pub mod heap;
+pub mod lock;
+
+pub use global::AsGlobal;
+
 #[cfg(feature = "bump")]
 pub mod bump;
 
@@ -32,6 +39,18 @@ pub mod statiq;
 #[cfg(feature = "system")]
 pub mod system;
 
+#[cfg(feature = "cache")]
+pub mod cache;
+
+#[cfg(feature = "compose")]
+pub mod compose;
+
+#[cfg(feature = "trace")]
+pub mod trace;
+
+#[cfg(any(test, feature = "model"))]
+pub mod model;
+
 pub const fn align_up(addr: usize, align: usize) -> usize {
     // Since align is a power of two, its binary representation has only a single bit set (e.g. 0b000100000). This means that align - 1 has all the lower bits set (e.g. 0b00011111).
     // By creating the bitwise NOT through the ! operator, we get a number that has all the bits set except for the bits lower than align (e.g. 0b…111111111100000).
@@ -53,6 +72,59 @@ pub const fn align_down(addr: usize, align: usize) -> usize {
     }
 }
 
+/// The prefix and suffix put around each block, like `compose::Affix` does, so an allocator can
+/// plan a heap for the layouts it's really asked for. `Affixes::NONE` keeps them as they are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Affixes {
+    pub prefix: Layout,
+    pub suffix: Layout,
+}
+
+impl Affixes {
+    pub const NONE: Self = Self::of::<(), ()>();
+
+    pub const fn of<P, S>() -> Self {
+        Self {
+            prefix: Layout::new::<P>(),
+            suffix: Layout::new::<S>(),
+        }
+    }
+
+    /// Returns the layout asked for `layout`, the offset of the block in it and the offset of the
+    /// suffix.
+    pub const fn outer(self, layout: Layout) -> Option<(Layout, usize, usize)> {
+        const fn max(x: usize, y: usize) -> usize {
+            if x > y {
+                x
+            } else {
+                y
+            }
+        }
+        // The block is aligned to the prefix, so the prefix can be found just before it.
+        let align = max(layout.align(), self.prefix.align());
+        let offset = align_up(self.prefix.size(), align);
+        // `layout.size()` is at most `isize::MAX`, so it can't overflow, and too big outer
+        // layouts are rejected below.
+        let suffix = align_up(offset + layout.size(), self.suffix.align());
+        let align = max(align, self.suffix.align());
+        match Layout::from_size_align(suffix + self.suffix.size(), align) {
+            Ok(outer) => Some((outer, offset, suffix)),
+            Err(_) => None,
+        }
+    }
+
+    /// The layout to plan for `layout`. Zero-sized ones stay so, as they don't touch the heap.
+    pub const fn plan(self, layout: Layout) -> Option<Layout> {
+        if layout.size() == 0 {
+            return Some(layout);
+        }
+        match self.outer(layout) {
+            Some((outer, _, _)) => Some(outer),
+            None => None,
+        }
+    }
+}
+
 pub trait Alloqator: Allocator {
     type Metadata;
 
@@ -71,29 +143,99 @@ pub trait Alloqator: Allocator {
     /// Can corrupt previous allocation it they was not deallocated. Make sure to deallocate
     /// everything before call it.
     unsafe fn hard_reset(&self) {
+        // An empty range can be null, like `system`'s.
         let len = self.heap_end().offset_from(self.heap_start()) as usize;
-        core::slice::from_raw_parts_mut(self.heap_start(), len).fill(0);
+        if len != 0 {
+            core::slice::from_raw_parts_mut(self.heap_start(), len).fill(0);
+        }
         self.reset();
     }
 
+    /// The smallest heap that fits allocating `layouts`, in this order and without deallocating,
+    /// when it starts aligned to all of them and to `Metadata` (like `heap::from_range` does). A
+    /// byte less can't fit them. It's `None` if it overflows or can't be planned ahead, which is
+    /// the default. This crate's allocators also have it as a `const fn`, for static heaps.
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize>
+    where
+        Self: Sized,
+    {
+        let _ = layouts;
+        None
+    }
+
+    /// `required_heap_size` for blocks with `affixes` around each of them, which is how
+    /// `compose::Affix` plans its inner allocator. Without affixes, it's `required_heap_size`,
+    /// and `None` otherwise by default.
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize>
+    where
+        Self: Sized,
+    {
+        if affixes == Affixes::NONE {
+            Self::required_heap_size(layouts)
+        } else {
+            None
+        }
+    }
+
     fn heap_start(&self) -> *mut u8;
     fn heap_end(&self) -> *mut u8;
     fn heap_range(&self) -> Range<*mut u8> {
         self.heap_start()..self.heap_end()
     }
 
+    /// How many bytes the block at `ptr` really has: `layout.size()` plus the slack that nobody
+    /// else can use, like alignment padding before the next metadata or the rest of a chunk.
+    /// It's the length `allocate` returns and, by default, just `layout.size()`.
+    /// # Safety
+    /// `ptr` must be allocated by this allocator with `layout`.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        let _ = ptr;
+        layout.size()
+    }
+
+    /// Like `GlobalAlloc::alloc`, returns null if it can't allocate.
     fn alloq(&self, layout: Layout) -> *mut u8 {
-        unsafe { (*self.allocate(layout).unwrap().as_ptr()).as_ptr() as *mut u8 }
+        self.allocate(layout)
+            .map_or(core::ptr::null_mut(), |block| block.as_ptr().cast())
     }
 
+    /// Deallocating null does nothing.
     /// # Safety
     /// It should just be called ONCE per allocation. Some allocators like `crate::list` and
     /// `crate::system` can handle it and panic, others will silently start to cause UB.
     unsafe fn dealloq(&self, ptr: *mut u8, layout: Layout) {
-        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.deallocate(ptr, layout);
+        }
     }
 }
 
+/// The block returned for zero-sized layouts: dangling, aligned to `layout`, and without touching
+/// the allocator's state. Deallocating it does nothing, so any number of ZSTs can be allocated.
+#[inline(always)]
+pub const fn dangling(layout: Layout) -> NonNull<[u8]> {
+    let ptr = unsafe { NonNull::new_unchecked(core::ptr::without_provenance_mut(layout.align())) };
+    NonNull::slice_from_raw_parts(ptr, 0)
+}
+
+/// Moves a block to a new allocation of `new_layout`, copying what fits. It's what `grow` and
+/// `shrink` do by default, for allocators that can only resize some blocks in place.
+/// # Safety
+/// Same as `Allocator::grow`.
+pub unsafe fn move_block<A: Allocator + ?Sized>(
+    alloqer: &A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let new = alloqer.allocate(new_layout)?;
+    let count = old_layout.size().min(new_layout.size());
+    core::ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr().cast(), count);
+    alloqer.deallocate(ptr, old_layout);
+    Ok(new)
+}
+
+#[deprecated(note = "it's just an estimate, use `Alloqator::required_heap_size` instead")]
 pub const fn get_size_hint_in<T, A: Alloqator>(count: usize) -> usize {
     const fn max(x: usize, y: usize) -> usize {
         if x > y {
@@ -103,12 +245,44 @@ pub const fn get_size_hint_in<T, A: Alloqator>(count: usize) -> usize {
         }
     }
     let meta_align = max(mem::align_of::<A::Metadata>(), 1);
-    let obj_align = max(mem::align_of::<A::Metadata>(), 1);
+    let obj_align = max(mem::align_of::<T>(), 1);
     (mem::size_of::<T>() + obj_align - 1 + mem::size_of::<A::Metadata>() + meta_align - 1) * count
 }
+/// Implements `GlobalAlloc` (through `AsGlobal`), `Send` and `Sync` for an `Alloqator` of this
+/// crate. Generic parameters need their bounds, e.g. `impl_allocator!(Alloq<L: RawLock>)`, and
+/// `Send`/`Sync` are only implemented when they are also `Send`/`Sync`. Other crates should wrap
+/// their allocators in `AsGlobal` instead.
 #[macro_export]
 macro_rules! impl_allocator {
-    ($typ:ty) => {
-        include!("alloqator.template.rs");
+    ($typ:ident $(<$($gen:ident: $bound:path),+>)?) => {
+        unsafe impl$(<$($gen: $bound),+>)? core::alloc::GlobalAlloc for $typ$(<$($gen),+>)? {
+            #[inline(always)]
+            unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+                $crate::AsGlobal::from_ref(self).alloc(layout)
+            }
+
+            #[inline(always)]
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+                $crate::AsGlobal::from_ref(self).dealloc(ptr, layout)
+            }
+
+            #[inline(always)]
+            unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+                $crate::AsGlobal::from_ref(self).alloc_zeroed(layout)
+            }
+
+            #[inline(always)]
+            unsafe fn realloc(
+                &self,
+                ptr: *mut u8,
+                layout: core::alloc::Layout,
+                new_size: usize,
+            ) -> *mut u8 {
+                $crate::AsGlobal::from_ref(self).realloc(ptr, layout, new_size)
+            }
+        }
+
+        unsafe impl$(<$($gen: $bound + Send),+>)? Send for $typ$(<$($gen),+>)? {}
+        unsafe impl$(<$($gen: $bound + Sync),+>)? Sync for $typ$(<$($gen),+>)? {}
     };
 }
@@ -7,7 +7,11 @@ use core::{
     slice,
 };
 
-use crate::Alloqator;
+use crate::{
+    fragmentation::{HeapWalk, Region},
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AlloqMetaData {
@@ -17,13 +21,15 @@ pub struct AlloqMetaData {
 }
 
 impl AlloqMetaData {
+    /// Fails if the block doesn't fit between `list` and its next block (or the heap's end). The
+    /// block's `end` goes until where the next node could start, see `Alloqator::usable_size`.
     /// # Safety
     /// `range` must be a valid heap block.
     pub unsafe fn allocate(
         list: *mut Self,
         range: Range<*mut u8>,
         layout: Layout,
-    ) -> (Self, *mut Self) {
+    ) -> Result<(Self, *mut Self), AllocError> {
         let range_start = if list.is_null() {
             range.start
         } else {
@@ -34,22 +40,18 @@ impl AlloqMetaData {
         } else {
             (*list).next.cast::<u8>().cast_const()
         };
-        let aligned_meta =
-            crate::align_up(range_start as usize, mem::align_of::<Self>()) as *mut u8;
-        let aligned_val = crate::align_up(
-            aligned_meta.add(mem::size_of::<Self>()) as usize,
-            layout.align(),
-        ) as *mut u8;
+        let aligned_meta = crate::align_up(range_start as usize, mem::align_of::<Self>());
+        let aligned_val = crate::align_up(aligned_meta + mem::size_of::<Self>(), layout.align());
+        let end = match aligned_val.checked_add(layout.size()) {
+            Some(end) if end <= range_end as usize => end,
+            _ => return Err(AllocError),
+        };
         let s = Self {
-            end: aligned_val.add(layout.size()),
+            end: range.start.with_addr(Self::block_end(end, range_end)),
             next: ptr::null_mut(),
             back: list,
         };
-        assert!(
-            aligned_val.add(layout.size()).cast_const() <= range_end,
-            "no available memory, end is {range_end:?}"
-        );
-        let s_ptr = s.write(aligned_meta);
+        let s_ptr = s.write(range.start.with_addr(aligned_meta));
         if !list.is_null() {
             let list_obj = list.as_mut().unwrap();
             let s_obj = s_ptr.as_mut().unwrap();
@@ -58,7 +60,7 @@ impl AlloqMetaData {
             }
             Self::connect_unchecked(list_obj, s_obj);
         }
-        (s, s_ptr)
+        Ok((s, s_ptr))
     }
 
     /// # Safety
@@ -87,10 +89,26 @@ impl AlloqMetaData {
         next.back = back as *mut Self;
     }
 
+    /// Where a block with its object ending at `obj_end` really ends: the padding until the next
+    /// node's alignment is also its, but not past `limit`.
+    #[inline(always)]
+    pub fn block_end(obj_end: usize, limit: *const u8) -> usize {
+        crate::align_up(obj_end, mem::align_of::<Self>()).min(limit as usize)
+    }
+
+    /// Returns if `ptr` is the block of `node`. Blocks go from after their node until `end`,
+    /// so it finds it even if it's deallocated with a bigger `Layout` that still fits.
+    /// # Safety
+    /// `node` must be valid.
+    #[inline(always)]
+    pub unsafe fn holds(node: *const Self, ptr: *const u8) -> bool {
+        node.cast::<u8>() < ptr && ptr < (*node).end
+    }
+
     pub fn end_of_allocation(ptr: *mut u8, layout: Layout) -> *mut u8 {
         let align = crate::align_up(ptr as usize, mem::align_of::<Self>());
         let obj_align = crate::align_up(align + mem::size_of::<Self>(), layout.align());
-        (obj_align + layout.size()) as *mut u8
+        obj_align.saturating_add(layout.size()) as *mut u8
     }
 
     pub fn iter(&self) -> AlloqMetaDataIter {
@@ -123,12 +141,14 @@ pub trait AllocMethod {
     unsafe fn remove(
         first_and_end: &mut (*mut AlloqMetaData, *mut AlloqMetaData),
         ptr: *mut u8,
-        layout: Layout,
+        _layout: Layout,
     ) {
-        let ptr_end = unsafe { ptr.add(layout.size()) };
         unsafe {
-            if first_and_end.1.as_ref().unwrap().end == ptr_end {
-                first_and_end.1 = first_and_end.1.as_ref().unwrap().back;
+            let last = first_and_end.1;
+            if AlloqMetaData::holds(last, ptr) {
+                first_and_end.1 = (*last).back;
+                // Unlinks it too, otherwise its area would never be reused.
+                (*last).disconnect();
                 return;
             }
         }
@@ -138,7 +158,7 @@ pub trait AllocMethod {
                 .as_ref()
                 .unwrap()
                 .iter()
-                .find(|&n| (*n).end == ptr_end)
+                .find(|&n| AlloqMetaData::holds(n, ptr))
         }
         .expect("use after free");
         unsafe { *node.cast_mut() }.disconnect();
@@ -156,7 +176,7 @@ impl AllocMethod for FirstFit {
                 return node_ptr.cast_mut();
             }
         }
-        panic!("no available memory");
+        unreachable!("the last block is always a fit");
     }
 }
 
@@ -192,53 +212,190 @@ impl AllocMethod for BestFit {
     }
 }
 
-pub struct Alloq<A: AllocMethod = FirstFit> {
+pub struct Alloq<A: AllocMethod = FirstFit, L: RawLock = Spin> {
     pub heap_start: *mut u8,
     pub heap_end: *mut u8,
-    pub first: spin::Mutex<(*mut AlloqMetaData, *mut AlloqMetaData)>,
+    pub first: Mutex<L, (*mut AlloqMetaData, *mut AlloqMetaData)>,
     pub _marker: PhantomData<A>,
 }
 
-impl<A: AllocMethod> Alloq<A> {}
+impl<A: AllocMethod, L: RawLock> Alloq<A, L> {
+    /// `Alloqator::required_heap_size`, but `const`.
+    pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, Affixes::NONE)
+    }
+
+    /// `Alloqator::required_affixed_heap_size`, but `const`. Counts the first block (a byte)
+    /// and each block's node. Without deallocations, the gaps between nodes can't fit another
+    /// one, so both methods just put blocks after the last one.
+    pub const fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        const META: usize = mem::size_of::<AlloqMetaData>();
+        const META_ALIGN: usize = mem::align_of::<AlloqMetaData>();
+        let mut end = META + 1;
+        let mut i = 0;
+        while i < layouts.len() {
+            let layout = match affixes.plan(layouts[i]) {
+                Some(layout) => layout,
+                None => return None,
+            };
+            i += 1;
+            if layout.size() == 0 {
+                continue;
+            }
+            let meta = crate::align_up(end, META_ALIGN);
+            end = match crate::align_up(meta + META, layout.align()).checked_add(layout.size()) {
+                Some(end) if end < usize::MAX - META - META_ALIGN => end,
+                _ => return None,
+            };
+        }
+        Some(end)
+    }
+
+    /// Moves the block's end, if `ptr` is aligned to `new_layout` and there's room until the next
+    /// block (or the heap's end). Returns `None` otherwise.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        new_layout: Layout,
+    ) -> Result<Option<NonNull<[u8]>>, AllocError> {
+        let lock = self.first.acquire()?;
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            return Ok(None);
+        }
+        let node = Self::node_of(&lock, ptr.as_ptr()).cast_mut();
+        let limit = if (*node).next.is_null() {
+            self.heap_end
+        } else {
+            (*node).next.cast()
+        };
+        let new_end = ptr.as_ptr().wrapping_add(new_layout.size());
+        if new_end > limit {
+            return Ok(None);
+        }
+        (*node).end = ptr
+            .as_ptr()
+            .with_addr(AlloqMetaData::block_end(new_end as usize, limit));
+        let usable = (*node).end.offset_from(ptr.as_ptr()) as usize;
+        Ok(Some(NonNull::slice_from_raw_parts(ptr, usable)))
+    }
+
+    /// The node of the block at `ptr`.
+    /// # Safety
+    /// `ptr` must be allocated by this allocator.
+    unsafe fn node_of(
+        first_and_end: &(*mut AlloqMetaData, *mut AlloqMetaData),
+        ptr: *const u8,
+    ) -> *const AlloqMetaData {
+        if AlloqMetaData::holds(first_and_end.1, ptr) {
+            return first_and_end.1;
+        }
+        (*first_and_end.0)
+            .iter()
+            .find(|&n| AlloqMetaData::holds(n, ptr))
+            .expect("use after free")
+    }
+}
 
-unsafe impl<A: AllocMethod> Allocator for Alloq<A> {
+unsafe impl<A: AllocMethod, L: RawLock> Allocator for Alloq<A, L> {
     /// Allocates a block according to where `A::fit` says that would be adequated.
     fn allocate(
         &self,
         layout: core::alloc::Layout,
     ) -> Result<core::ptr::NonNull<[u8]>, core::alloc::AllocError> {
-        let mut lock = self.first.lock();
-        let ptr = unsafe {
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
+        }
+        let mut lock = self.first.acquire()?;
+        let (ptr, end) = unsafe {
             let back = A::fit((lock.0.as_mut().unwrap(), lock.1.as_mut().unwrap()), layout);
-            let meta = AlloqMetaData::allocate(back.cast_mut(), self.heap_range(), layout);
+            let meta = AlloqMetaData::allocate(back.cast_mut(), self.heap_range(), layout)?;
             if meta.1 > lock.1 {
                 lock.1 = meta.1;
             }
-            meta.0.end.offset(-(layout.size() as isize))
+            let obj = crate::align_up(meta.1.add(1) as usize, layout.align());
+            (meta.1.cast::<u8>().with_addr(obj), meta.0.end)
         };
-        let slice = unsafe { slice::from_raw_parts_mut(ptr.cast_mut(), layout.size()) };
+        let usable = end as usize - ptr as usize;
+        let slice = unsafe { slice::from_raw_parts_mut(ptr, usable) };
         NonNull::new(slice).ok_or(AllocError)
     }
 
-    /// Goes over the linked list searching by a correspondent `end`.
+    /// Goes over the linked list searching the node holding `ptr`.
     /// Unsafe:
     /// - `first` must refer to a valid and never-deallocate block.
     unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: core::alloc::Layout) {
-        let lock = self.first.lock();
-        A::remove(&mut (lock.0, lock.1), ptr.as_ptr(), layout);
+        if layout.size() == 0 {
+            return;
+        }
+        let mut lock = self.first.lock();
+        A::remove(&mut lock, ptr.as_ptr(), layout);
+    }
+
+    /// In place if there's room until the next block.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+        if let Some(block) = self.resize(ptr, new_layout)? {
+            return Ok(block);
+        }
+        crate::move_block(self, ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.grow(ptr, old_layout, new_layout)?;
+        let tail = block.cast::<u8>().add(old_layout.size());
+        tail.write_bytes(0, block.len() - old_layout.size());
+        Ok(block)
+    }
+
+    /// In place, unless `ptr` isn't aligned to `new_layout`.
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(crate::dangling(new_layout));
+        }
+        if let Some(block) = self.resize(ptr, new_layout)? {
+            return Ok(block);
+        }
+        crate::move_block(self, ptr, old_layout, new_layout)
     }
 }
 
-impl<A: AllocMethod> Alloqator for Alloq<A> {
+impl<A: AllocMethod, L: RawLock> Alloqator for Alloq<A, L> {
     type Metadata = AlloqMetaData;
 
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, affixes)
+    }
+
     fn new(heap_range: core::ops::Range<*mut u8>) -> Self
     where
         Self: Sized,
     {
         let offset = unsafe {
             AlloqMetaData::allocate(ptr::null_mut(), heap_range.clone(), Layout::new::<u8>())
-        };
+        }
+        .expect("list: the heap can't even fit the first block");
         Self {
             heap_start: heap_range.start,
             heap_end: heap_range.end,
@@ -251,7 +408,8 @@ impl<A: AllocMethod> Alloqator for Alloq<A> {
         let mut lock = self.first.lock();
         let offset = unsafe {
             AlloqMetaData::allocate(ptr::null_mut(), self.heap_range(), Layout::new::<u8>())
-        };
+        }
+        .expect("list: the heap can't even fit the first block");
         lock.0 = offset.1;
         lock.1 = offset.1;
     }
@@ -263,6 +421,348 @@ impl<A: AllocMethod> Alloqator for Alloq<A> {
     fn heap_end(&self) -> *mut u8 {
         self.heap_end
     }
+
+    /// Until its node's `end`, which also has the padding before the next node.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if layout.size() == 0 {
+            return 0;
+        }
+        let lock = self.first.lock();
+        (*Self::node_of(&lock, ptr.as_ptr()))
+            .end
+            .offset_from(ptr.as_ptr()) as usize
+    }
+}
+
+impl<A: AllocMethod, L: RawLock> HeapWalk for Alloq<A, L> {
+    /// Nodes are metadata, with the padding before them and the first block (a byte). Each block
+    /// goes until its node's `end`, and the gap until the next node is free.
+    fn walk(&self, f: &mut dyn FnMut(Range<*mut u8>, Region)) {
+        let lock = self.first.lock();
+        let at = |addr: *const u8| self.heap_start.with_addr(addr as usize);
+        let mut start = self.heap_start;
+        for node in unsafe { (*lock.0).iter() } {
+            let node_end = unsafe { node.add(1) }.cast::<u8>();
+            let end = at(unsafe { (*node).end });
+            if node == lock.0.cast_const() {
+                f(start..end, Region::Metadata);
+            } else {
+                f(start..at(node_end), Region::Metadata);
+                f(at(node_end)..end, Region::Used);
+            }
+            start = match unsafe { (*node).next } {
+                next if next.is_null() => self.heap_end,
+                next => at(next.cast()),
+            };
+            if end < start {
+                f(end..start, Region::Free);
+            }
+        }
+    }
+}
+
+crate::impl_allocator!(Alloq<A: AllocMethod, L: RawLock>);
+
+/// Handles to blocks that `compact` can move, for long-running heaps that fragment. Blocks
+/// allocated through a handle slide toward `heap_start` on `compact`, unless pinned; the ones
+/// allocated directly from `list` never move.
+pub mod reloc {
+    use core::{
+        alloc::{AllocError, Layout},
+        mem,
+        ops::Range,
+        ptr::{self, NonNull},
+    };
+
+    use super::{AllocMethod, AlloqMetaData, FirstFit};
+    use crate::{
+        lock::{Mutex, RawLock, Spin},
+        Alloqator,
+    };
+
+    /// A block of `Alloq`. Its address changes on each `compact`, so get it again after that, or
+    /// `pin` the block while using it.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct RelocHandle(usize);
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct Slot {
+        /// Null when free.
+        pub ptr: *mut u8,
+        pub layout: Layout,
+        pub pins: usize,
+    }
+
+    impl Slot {
+        pub const EMPTY: Self = Self {
+            ptr: ptr::null_mut(),
+            layout: Layout::new::<()>(),
+            pins: 0,
+        };
+
+        #[inline(always)]
+        pub fn block(&self) -> NonNull<[u8]> {
+            NonNull::slice_from_raw_parts(NonNull::new(self.ptr).unwrap(), self.layout.size())
+        }
+    }
+
+    /// `list::Alloq` with up to `HANDLES` relocatable blocks at once.
+    pub struct Alloq<A: AllocMethod = FirstFit, L: RawLock = Spin, const HANDLES: usize = 64> {
+        pub list: super::Alloq<A, L>,
+        pub slots: Mutex<L, [Slot; HANDLES]>,
+    }
+
+    unsafe impl<A: AllocMethod, L: RawLock + Send, const HANDLES: usize> Send for Alloq<A, L, HANDLES> {}
+    unsafe impl<A: AllocMethod, L: RawLock + Sync, const HANDLES: usize> Sync for Alloq<A, L, HANDLES> {}
+
+    impl<A: AllocMethod, L: RawLock, const HANDLES: usize> Alloq<A, L, HANDLES> {
+        pub const fn with(list: super::Alloq<A, L>) -> Self {
+            Self {
+                list,
+                slots: Mutex::new([Slot::EMPTY; HANDLES]),
+            }
+        }
+
+        pub fn new(heap_range: Range<*mut u8>) -> Self {
+            Self::with(super::Alloq::new(heap_range))
+        }
+
+        #[inline(always)]
+        fn slot(slots: &mut [Slot; HANDLES], handle: RelocHandle) -> &mut Slot {
+            let slot = &mut slots[handle.0];
+            assert!(!slot.ptr.is_null(), "dangling handle");
+            slot
+        }
+
+        /// Fails if `list` is out of memory or there's no free handle.
+        pub fn allocate(&self, layout: Layout) -> Result<RelocHandle, AllocError> {
+            let mut slots = self.slots.acquire()?;
+            let index = slots
+                .iter()
+                .position(|slot| slot.ptr.is_null())
+                .ok_or(AllocError)?;
+            let block = core::alloc::Allocator::allocate(&self.list, layout)?;
+            slots[index] = Slot {
+                ptr: block.as_ptr().cast(),
+                layout,
+                pins: 0,
+            };
+            Ok(RelocHandle(index))
+        }
+
+        /// # Safety
+        /// `handle` and the addresses got from it can't be used after.
+        pub unsafe fn deallocate(&self, handle: RelocHandle) {
+            let mut slots = self.slots.lock();
+            let slot = Self::slot(&mut slots, handle);
+            assert_eq!(slot.pins, 0, "deallocating a pinned block");
+            core::alloc::Allocator::deallocate(
+                &self.list,
+                NonNull::new_unchecked(slot.ptr),
+                slot.layout,
+            );
+            *slot = Slot::EMPTY;
+        }
+
+        /// The current address of the block, valid until the next `compact`.
+        pub fn get(&self, handle: RelocHandle) -> NonNull<[u8]> {
+            let mut slots = self.slots.lock();
+            let slot = Self::slot(&mut slots, handle);
+            slot.block()
+        }
+
+        /// Keeps the block in place until the guard is dropped.
+        pub fn pin(&self, handle: RelocHandle) -> Pinned<'_, A, L, HANDLES> {
+            let mut slots = self.slots.lock();
+            let slot = Self::slot(&mut slots, handle);
+            slot.pins += 1;
+            Pinned {
+                alloq: self,
+                handle,
+                block: slot.block(),
+            }
+        }
+
+        /// Slides every unpinned block with a handle toward `heap_start`, right after the block
+        /// before it, and returns the bytes free after the last block. Blocks without a handle and
+        /// pinned ones stay, so the blocks after them only close the gap up to them.
+        /// Goes over the list once, checking every handle for each block.
+        pub fn compact(&self) -> usize {
+            let mut slots = self.slots.lock();
+            let mut lock = self.list.first.lock();
+            unsafe {
+                let mut back = lock.0;
+                let mut node = (*back).next;
+                while !node.is_null() {
+                    let old = *node;
+                    let meta =
+                        crate::align_up((*back).end as usize, mem::align_of::<AlloqMetaData>());
+                    let target = slots
+                        .iter_mut()
+                        .find(|slot| slot.pins == 0 && AlloqMetaData::holds(node, slot.ptr))
+                        .map(|slot| {
+                            let align = slot.layout.align();
+                            let obj =
+                                crate::align_up(meta + mem::size_of::<AlloqMetaData>(), align);
+                            (slot, obj)
+                        })
+                        .filter(|(slot, obj)| *obj < slot.ptr as usize);
+                    let Some((slot, obj)) = target else {
+                        back = node;
+                        node = old.next;
+                        continue;
+                    };
+                    // The whole block, with its slack.
+                    let len = old.end.offset_from(slot.ptr) as usize;
+                    let moved_obj = slot.ptr.with_addr(obj);
+                    ptr::copy(slot.ptr, moved_obj, len);
+                    let moved = AlloqMetaData {
+                        end: moved_obj.add(len),
+                        next: old.next,
+                        back,
+                    }
+                    .write(moved_obj.with_addr(meta));
+                    (*back).next = moved;
+                    if !old.next.is_null() {
+                        (*old.next).back = moved;
+                    }
+                    if lock.1 == node {
+                        lock.1 = moved;
+                    }
+                    slot.ptr = moved_obj;
+                    back = moved;
+                    node = old.next;
+                }
+                self.list.heap_end.offset_from((*lock.1).end) as usize
+            }
+        }
+    }
+
+    /// A pinned block, which `compact` doesn't move while this lives.
+    pub struct Pinned<'a, A: AllocMethod, L: RawLock, const HANDLES: usize> {
+        alloq: &'a Alloq<A, L, HANDLES>,
+        pub handle: RelocHandle,
+        pub block: NonNull<[u8]>,
+    }
+
+    impl<A: AllocMethod, L: RawLock, const HANDLES: usize> Drop for Pinned<'_, A, L, HANDLES> {
+        fn drop(&mut self) {
+            self.alloq.slots.lock()[self.handle.0].pins -= 1;
+        }
+    }
+
+    #[cfg(test)]
+    pub mod tests {
+        extern crate alloc;
+
+        use super::{Alloq, RelocHandle};
+        use core::alloc::{Allocator, Layout};
+
+        const SIZE: usize = 400;
+
+        fn fill(alloqer: &Alloq, i: u8) -> RelocHandle {
+            let handle = alloqer.allocate(Layout::new::<[u8; SIZE]>()).unwrap();
+            unsafe { alloqer.get(handle).as_mut() }.fill(i);
+            handle
+        }
+
+        fn check(alloqer: &Alloq, handle: RelocHandle, i: u8) {
+            let block = unsafe { alloqer.get(handle).as_ref() };
+            assert!(block.iter().all(|&b| b == i), "block {i} corrupted");
+        }
+
+        #[test]
+        fn compact() {
+            let mut heap = alloc::vec![0u8; 4096];
+            let alloqer = <Alloq>::new(heap.as_mut_ptr_range());
+            let handles: alloc::vec::Vec<_> = (0..8).map(|i| fill(&alloqer, i)).collect();
+            for &handle in handles.iter().step_by(2) {
+                unsafe { alloqer.deallocate(handle) };
+            }
+            let big = Layout::new::<[u8; SIZE * 4]>();
+            assert!(
+                alloqer.list.allocate(big).is_err(),
+                "the heap isn't fragmented"
+            );
+            let before: alloc::vec::Vec<_> = handles
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .map(|&h| alloqer.get(h))
+                .collect();
+            let free = alloqer.compact();
+            for (i, &handle) in handles.iter().enumerate().skip(1).step_by(2) {
+                check(&alloqer, handle, i as u8);
+                assert!(alloqer.get(handle).cast::<u8>() < before[i / 2].cast::<u8>());
+            }
+            assert!(free >= big.size());
+            let block = alloqer
+                .list
+                .allocate(big)
+                .expect("the tail wasn't reclaimed");
+            unsafe { alloqer.list.deallocate(block.cast(), big) };
+            assert_eq!(alloqer.compact(), free, "nothing more to compact");
+        }
+
+        #[test]
+        fn pinned_and_plain_blocks_stay() {
+            let mut heap = alloc::vec![0u8; 4096];
+            let alloqer = <Alloq>::new(heap.as_mut_ptr_range());
+            let layout = Layout::new::<[u8; SIZE]>();
+            let a = fill(&alloqer, 0);
+            let b = fill(&alloqer, 1);
+            let c = fill(&alloqer, 2);
+            let plain = alloqer.list.allocate(layout).unwrap();
+            let d = fill(&alloqer, 3);
+            unsafe { alloqer.deallocate(a) };
+            let c_before = alloqer.get(c);
+            {
+                let pinned = alloqer.pin(b);
+                alloqer.compact();
+                assert_eq!(alloqer.get(b), pinned.block, "a pinned block moved");
+                assert_eq!(alloqer.get(c), c_before, "`b` is in the way");
+            }
+            check(&alloqer, b, 1);
+            check(&alloqer, c, 2);
+            let d_before = alloqer.get(d);
+            alloqer.compact();
+            assert_eq!(alloqer.get(d), d_before, "a plain block is in the way");
+            unsafe { alloqer.list.deallocate(plain.cast(), layout) };
+            alloqer.compact();
+            assert!(alloqer.get(b).cast::<u8>() < c_before.cast::<u8>());
+            assert!(alloqer.get(d).cast::<u8>() < d_before.cast::<u8>());
+            check(&alloqer, b, 1);
+            check(&alloqer, c, 2);
+            check(&alloqer, d, 3);
+        }
+
+        #[test]
+        fn over_aligned() {
+            let mut heap = alloc::vec![0u8; 8192];
+            let alloqer = <Alloq>::new(heap.as_mut_ptr_range());
+            let layout = Layout::from_size_align(100, 512).unwrap();
+            let a = fill(&alloqer, 0);
+            let b = alloqer.allocate(layout).unwrap();
+            unsafe { alloqer.get(b).as_mut() }.fill(1);
+            unsafe { alloqer.deallocate(a) };
+            alloqer.compact();
+            assert!((alloqer.get(b).cast::<u8>().as_ptr() as usize).is_multiple_of(512));
+            check(&alloqer, b, 1);
+        }
+
+        #[test]
+        fn out_of_handles() {
+            let mut heap = alloc::vec![0u8; 4096];
+            let alloqer =
+                Alloq::<super::FirstFit, crate::lock::Spin, 2>::new(heap.as_mut_ptr_range());
+            let layout = Layout::new::<u64>();
+            let a = alloqer.allocate(layout).unwrap();
+            alloqer.allocate(layout).unwrap();
+            assert!(alloqer.allocate(layout).is_err());
+            unsafe { alloqer.deallocate(a) };
+            assert!(alloqer.allocate(layout).is_ok());
+        }
+    }
 }
 
 pub mod first {
@@ -274,7 +774,66 @@ pub mod first {
     pub mod tests {
         use super::Alloq;
 
+        const PLANS_HEAP: bool = true;
+
         include!("test.template.rs");
+
+        #[test]
+        fn resize_in_place() {
+            let mut heap = [0u8; 1024 * 4];
+            let alloqer = Alloq::new(heap.as_mut_ptr_range());
+            let mut v = Vec::with_capacity_in(4, &alloqer);
+            v.extend(0..4u32);
+            let ptr = v.as_ptr();
+            v.extend(4..64);
+            assert_eq!(v.as_ptr(), ptr, "the last block must grow in place");
+            let mut w = Vec::with_capacity_in(64, &alloqer);
+            w.extend(0..64u32);
+            v.truncate(8);
+            v.shrink_to_fit();
+            assert_eq!(v.as_ptr(), ptr, "shrinking is in place");
+            v.extend(8..32);
+            assert_eq!(v.as_ptr(), ptr, "the freed room can be grown into");
+            v.extend(32..128);
+            assert_ne!(v.as_ptr(), ptr, "`w` is in the way");
+            assert_eq!(v.iter().sum::<u32>(), (0..128).sum());
+            assert_eq!(w.iter().sum::<u32>(), (0..64).sum());
+        }
+
+        #[test]
+        fn fragmentation() {
+            use crate::fragmentation::{HeapWalk, Region};
+
+            let mut heap = [0u8; 1024 * 4];
+            let alloqer = Alloq::new(heap.as_mut_ptr_range());
+            let report = alloqer.fragmentation();
+            assert_eq!((report.free_blocks, report.external()), (1, 0.0));
+            let layout = Layout::new::<[u8; 100]>();
+            let ptrs: Vec<_> = (0..3).map(|_| alloqer.alloq(layout)).collect();
+            unsafe { alloqer.dealloq(ptrs[1], layout) };
+            let report = alloqer.fragmentation();
+            assert_eq!(report.free_blocks, 2);
+            assert_eq!(report.histogram.iter().sum::<usize>(), 2);
+            assert!(report.external() > 0.0);
+            assert_eq!(report.used + report.free + report.metadata, heap.len());
+            let mut next = alloqer.heap_start();
+            alloqer.walk(&mut |range, region| {
+                assert_eq!(range.start, next, "regions must be contiguous");
+                assert!(
+                    region != Region::Used || range.contains(&ptrs[0]) || range.contains(&ptrs[2])
+                );
+                next = range.end;
+            });
+            assert_eq!(next, alloqer.heap_end());
+            let mut map = alloc::string::String::new();
+            alloqer.write_heap_map(&mut map).unwrap();
+            let sizes: usize = map
+                .lines()
+                .skip(1)
+                .map(|line| line.split(", ").nth(1).unwrap().parse::<usize>().unwrap())
+                .sum();
+            assert_eq!(sizes, heap.len());
+        }
     }
 }
 
@@ -287,6 +846,8 @@ pub mod best {
     pub mod tests {
         use super::Alloq;
 
+        const PLANS_HEAP: bool = true;
+
         include!("test.template.rs");
     }
 }
@@ -0,0 +1,639 @@
+use core::{
+    alloc::AllocError,
+    cell::{Cell, UnsafeCell},
+    fmt, hint,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// A lock without data, used by `Mutex` to protect the allocators' state. Pick the one that fits
+/// the target: `Spin` for SMP, `Critical` for single-core targets that allocate from interrupts,
+/// `IsrSafe` for SMP targets that allocate from interrupts, `NoLock` for single-threaded code, or
+/// implement it over an OS mutex on hosted targets.
+/// # Safety
+/// Between a `lock` (or a successful `try_lock`) and its `unlock`, no other context can acquire
+/// the lock.
+pub unsafe trait RawLock {
+    /// An unlocked lock.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self;
+
+    fn lock(&self);
+
+    /// Tries to acquire the lock without waiting. Returns `true` if acquired.
+    fn try_lock(&self) -> bool;
+
+    /// Acquires the lock for an allocation. Returns `false` instead of waiting when it would never
+    /// be released, e.g. when re-entered by an interrupt on the core that holds it, so the
+    /// allocation fails with `AllocError` instead of deadlocking.
+    #[inline(always)]
+    fn acquire(&self) -> bool {
+        self.lock();
+        true
+    }
+
+    /// # Safety
+    /// The lock must be held by the current context.
+    unsafe fn unlock(&self);
+}
+
+/// A busy-waiting lock. Works everywhere with atomic compare-and-swap, but deadlocks if the
+/// holder is interrupted by someone that takes the same lock.
+#[derive(Debug)]
+pub struct Spin(AtomicBool);
+
+unsafe impl RawLock for Spin {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self(AtomicBool::new(false));
+
+    #[inline(always)]
+    fn lock(&self) {
+        while self
+            .0
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.0.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// No lock at all, for single-threaded targets. Like a `RefCell`, it only tracks if it's held, so
+/// a re-entrant allocation panics instead of corrupting the heap. It isn't `Sync`, so allocators
+/// using it can't be shared between threads.
+#[derive(Debug)]
+pub struct NoLock(Cell<bool>);
+
+unsafe impl RawLock for NoLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self(Cell::new(false));
+
+    #[inline(always)]
+    fn lock(&self) {
+        assert!(self.try_lock(), "already locked: re-entrant allocation");
+    }
+
+    #[inline(always)]
+    fn try_lock(&self) -> bool {
+        !self.0.replace(true)
+    }
+
+    #[inline(always)]
+    fn acquire(&self) -> bool {
+        self.try_lock()
+    }
+
+    #[inline(always)]
+    unsafe fn unlock(&self) {
+        self.0.set(false);
+    }
+}
+
+/// Hooks to enter and leave a critical section, usually disabling and restoring interrupts.
+/// # Safety
+/// `enter` must give exclusive access until the matching `exit`: no other thread, core or
+/// interrupt handler can run code that enters the section in between. `Critical` relies on it
+/// to be a lock.
+pub unsafe trait CriticalSection {
+    /// What is needed to leave the section, e.g. the previous interrupt mask.
+    type State: Copy;
+
+    fn enter() -> Self::State;
+
+    fn exit(state: Self::State);
+}
+
+/// A lock that just runs the code inside `C`'s critical section. On a single-core target with
+/// interrupts disabled, nothing else can run, so it's enough to be exclusive.
+pub struct Critical<C: CriticalSection> {
+    state: UnsafeCell<Option<C::State>>,
+    _marker: PhantomData<C>,
+}
+
+// SAFE: the state is only touched inside the critical section.
+unsafe impl<C: CriticalSection> Sync for Critical<C> {}
+unsafe impl<C: CriticalSection> Send for Critical<C> {}
+
+impl<C: CriticalSection> fmt::Debug for Critical<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Critical")
+    }
+}
+
+unsafe impl<C: CriticalSection> RawLock for Critical<C> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        state: UnsafeCell::new(None),
+        _marker: PhantomData,
+    };
+
+    #[inline(always)]
+    fn lock(&self) {
+        let state = C::enter();
+        unsafe { *self.state.get() = Some(state) };
+    }
+
+    #[inline(always)]
+    fn try_lock(&self) -> bool {
+        self.lock();
+        true
+    }
+
+    #[inline(always)]
+    unsafe fn unlock(&self) {
+        let state = (*self.state.get())
+            .take()
+            .expect("unlocking an unlocked lock");
+        C::exit(state);
+    }
+}
+
+/// Tells which core is running. As it's `no_std`, the user must supply it (e.g. reading `mpidr`,
+/// `mhartid` or the APIC id).
+pub trait CoreId {
+    fn current() -> usize;
+}
+
+/// For single-core targets, always core 0.
+pub struct SingleCore;
+
+impl CoreId for SingleCore {
+    #[inline(always)]
+    fn current() -> usize {
+        0
+    }
+}
+
+/// An interrupt-safe spin lock: runs inside `C`'s critical section (so an interrupt can't take
+/// the lock from under its holder) and spins while another core holds it. If the core that
+/// holds it re-enters (e.g. from a NMI or a handler that `C` doesn't mask), `acquire` fails
+/// instead of spinning forever. Only allocations use `acquire`: deallocations, resets and
+/// `list::reloc`'s handles `lock`, which panics with "deadlock: lock re-entered" there, so such
+/// a handler must not free.
+pub struct IsrSafe<C: CriticalSection, I: CoreId = SingleCore> {
+    /// The holder's core plus one, or 0 if unlocked.
+    owner: AtomicUsize,
+    state: UnsafeCell<Option<C::State>>,
+    _marker: PhantomData<(C, I)>,
+}
+
+// SAFE: `state` is only touched by the lock's holder.
+unsafe impl<C: CriticalSection, I: CoreId> Sync for IsrSafe<C, I> {}
+unsafe impl<C: CriticalSection, I: CoreId> Send for IsrSafe<C, I> {}
+
+impl<C: CriticalSection, I: CoreId> fmt::Debug for IsrSafe<C, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IsrSafe")
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+unsafe impl<C: CriticalSection, I: CoreId> RawLock for IsrSafe<C, I> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        owner: AtomicUsize::new(0),
+        state: UnsafeCell::new(None),
+        _marker: PhantomData,
+    };
+
+    #[inline(always)]
+    fn lock(&self) {
+        assert!(
+            self.acquire(),
+            "deadlock: lock re-entered on core {}",
+            I::current()
+        );
+    }
+
+    #[inline(always)]
+    fn try_lock(&self) -> bool {
+        let state = C::enter();
+        let acquired = self
+            .owner
+            .compare_exchange(0, I::current() + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if acquired {
+            unsafe { *self.state.get() = Some(state) };
+        } else {
+            C::exit(state);
+        }
+        acquired
+    }
+
+    fn acquire(&self) -> bool {
+        let state = C::enter();
+        let core = I::current() + 1;
+        loop {
+            match self
+                .owner
+                .compare_exchange_weak(0, core, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    unsafe { *self.state.get() = Some(state) };
+                    return true;
+                }
+                Err(owner) if owner == core => {
+                    C::exit(state);
+                    return false;
+                }
+                Err(_) => hint::spin_loop(),
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn unlock(&self) {
+        let state = (*self.state.get())
+            .take()
+            .expect("unlocking an unlocked lock");
+        self.owner.store(0, Ordering::Release);
+        C::exit(state);
+    }
+}
+
+/// A mutual exclusion primitive over any `RawLock`.
+pub struct Mutex<L: RawLock, T> {
+    lock: L,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<L: RawLock + Send, T: Send> Send for Mutex<L, T> {}
+unsafe impl<L: RawLock + Sync, T: Send> Sync for Mutex<L, T> {}
+
+impl<L: RawLock, T> Mutex<L, T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            lock: L::INIT,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn lock(&self) -> MutexGuard<'_, L, T> {
+        self.lock.lock();
+        MutexGuard { mutex: self }
+    }
+
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, L, T>> {
+        self.lock.try_lock().then(|| MutexGuard { mutex: self })
+    }
+
+    /// Locks for an allocation, see `RawLock::acquire`.
+    #[inline(always)]
+    pub fn acquire(&self) -> Result<MutexGuard<'_, L, T>, AllocError> {
+        if self.lock.acquire() {
+            Ok(MutexGuard { mutex: self })
+        } else {
+            Err(AllocError)
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<L: RawLock, T> From<T> for Mutex<L, T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<L: RawLock, T: fmt::Debug> fmt::Debug for Mutex<L, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &*guard).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+pub struct MutexGuard<'a, L: RawLock, T> {
+    mutex: &'a Mutex<L, T>,
+}
+
+impl<L: RawLock, T> Deref for MutexGuard<'_, L, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<L: RawLock, T> DerefMut for MutexGuard<'_, L, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<L: RawLock, T> Drop for MutexGuard<'_, L, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.lock.unlock() };
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    extern crate std;
+
+    use super::{CoreId, Critical, CriticalSection, IsrSafe, Mutex, NoLock, RawLock, Spin};
+    use core::{
+        cell::Cell,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use std::thread;
+
+    static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+    std::thread_local! {
+        static ISR_DEPTH: Cell<usize> = const { Cell::new(0) };
+    }
+
+    struct NopSection;
+
+    // SAFETY: only used by single-threaded tests, without interrupts.
+    unsafe impl CriticalSection for NopSection {
+        type State = ();
+
+        fn enter() {}
+
+        fn exit(_: ()) {}
+    }
+
+    struct CountingSection;
+
+    // SAFETY: only used by single-threaded tests, without interrupts.
+    unsafe impl CriticalSection for CountingSection {
+        type State = usize;
+
+        fn enter() -> usize {
+            DEPTH.fetch_add(1, Ordering::Relaxed)
+        }
+
+        fn exit(state: usize) {
+            DEPTH.store(state, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn spin_exclusion() {
+        const THREADS: usize = 8;
+        const INCREMENTS: usize = 1000;
+        let counter = Mutex::<Spin, usize>::new(0);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..INCREMENTS {
+                        *counter.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.into_inner(), THREADS * INCREMENTS);
+    }
+
+    #[test]
+    fn try_lock() {
+        let m = Mutex::<Spin, ()>::new(());
+        let guard = m.lock();
+        assert!(m.try_lock().is_none());
+        drop(guard);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "re-entrant")]
+    fn no_lock_reentrancy() {
+        let m = Mutex::<NoLock, ()>::new(());
+        let _guard = m.lock();
+        let _ = m.lock();
+    }
+
+    /// Counts the nesting per thread, so tests running in parallel don't see each other.
+    struct ThreadSection;
+
+    // SAFETY: only used by `IsrSafe`, which excludes the other threads itself, and the tests
+    // re-enter it on purpose.
+    unsafe impl CriticalSection for ThreadSection {
+        type State = usize;
+
+        fn enter() -> usize {
+            ISR_DEPTH.with(|d| d.replace(d.get() + 1))
+        }
+
+        fn exit(state: usize) {
+            ISR_DEPTH.with(|d| d.set(state));
+        }
+    }
+
+    /// Each thread is a core.
+    struct ThreadCore;
+
+    impl CoreId for ThreadCore {
+        fn current() -> usize {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            std::thread_local! {
+                static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+            }
+            ID.with(|id| *id)
+        }
+    }
+
+    type Isr = IsrSafe<ThreadSection, ThreadCore>;
+
+    #[test]
+    fn critical_section_hooks() {
+        let m = Mutex::<Critical<CountingSection>, usize>::new(0);
+        {
+            let mut guard = m.lock();
+            *guard += 1;
+            assert_eq!(DEPTH.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(DEPTH.load(Ordering::Relaxed), 0);
+        assert_eq!(*m.lock(), 1);
+    }
+
+    #[test]
+    fn isr_safe_hooks() {
+        let m = Mutex::<Isr, usize>::new(0);
+        {
+            let mut guard = m.lock();
+            *guard += 1;
+            assert_eq!(ISR_DEPTH.with(|d| d.get()), 1);
+            assert!(m.try_lock().is_none());
+            assert_eq!(ISR_DEPTH.with(|d| d.get()), 1);
+        }
+        assert_eq!(ISR_DEPTH.with(|d| d.get()), 0);
+        assert_eq!(*m.lock(), 1);
+    }
+
+    #[test]
+    fn isr_safe_reentrancy() {
+        let m = Mutex::<Isr, ()>::new(());
+        let _guard = m.lock();
+        assert!(m.acquire().is_err());
+        assert_eq!(ISR_DEPTH.with(|d| d.get()), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "deadlock")]
+    fn isr_safe_lock_reentrancy() {
+        let m = Mutex::<Isr, ()>::new(());
+        let _guard = m.lock();
+        let _ = m.lock();
+    }
+
+    #[test]
+    fn isr_safe_exclusion() {
+        const THREADS: usize = 8;
+        const INCREMENTS: usize = 1000;
+        let counter = Mutex::<Isr, usize>::new(0);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..INCREMENTS {
+                        *counter.acquire().unwrap() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.into_inner(), THREADS * INCREMENTS);
+    }
+
+    #[test]
+    fn no_lock_acquire() {
+        let m = Mutex::<NoLock, ()>::new(());
+        let _guard = m.lock();
+        assert!(m.acquire().is_err());
+    }
+
+    #[cfg(any(
+        feature = "bump",
+        feature = "debump",
+        feature = "statiq",
+        feature = "list",
+        feature = "pool"
+    ))]
+    fn alloc_with<A: crate::Alloqator>() {
+        use core::alloc::Layout;
+
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = A::new(heap.as_mut_ptr_range());
+        let layout = Layout::new::<u64>();
+        let ptrs: std::vec::Vec<_> = (0..4).map(|_| alloqer.alloq(layout)).collect();
+        for ptr in ptrs {
+            unsafe { alloqer.dealloq(ptr, layout) };
+        }
+    }
+
+    fn alloc_with_lock<L: RawLock>() {
+        #[cfg(feature = "bump")]
+        alloc_with::<crate::bump::Alloq<L>>();
+        #[cfg(feature = "debump")]
+        alloc_with::<crate::debump::Alloq<L>>();
+        #[cfg(feature = "statiq")]
+        alloc_with::<crate::statiq::Alloq<L>>();
+        #[cfg(feature = "list")]
+        alloc_with::<crate::list::Alloq<crate::list::FirstFit, L>>();
+        #[cfg(feature = "pool")]
+        alloc_with::<crate::pool::Alloq<L>>();
+    }
+
+    #[test]
+    fn allocators_with_no_lock() {
+        alloc_with_lock::<NoLock>();
+    }
+
+    #[test]
+    fn allocators_with_critical() {
+        alloc_with_lock::<Critical<NopSection>>();
+    }
+
+    #[test]
+    fn allocators_with_isr_safe() {
+        alloc_with_lock::<Isr>();
+    }
+
+    /// An allocation from an interrupt while the same core is inside the allocator fails.
+    #[test]
+    #[cfg(any(feature = "bump", feature = "list", feature = "pool"))]
+    fn reentrant_allocation_fails() {
+        use crate::Alloqator;
+        use core::alloc::{Allocator, Layout};
+
+        let mut heap = [0u8; 1024 * 4];
+        let layout = Layout::new::<u64>();
+        #[cfg(feature = "bump")]
+        {
+            let alloqer = crate::bump::Alloq::<Isr>::new(heap.as_mut_ptr_range());
+            let _guard = alloqer.iter.lock();
+            assert!(alloqer.allocate(layout).is_err());
+        }
+        #[cfg(feature = "list")]
+        {
+            let alloqer =
+                crate::list::Alloq::<crate::list::FirstFit, Isr>::new(heap.as_mut_ptr_range());
+            let _guard = alloqer.first.lock();
+            assert!(alloqer.allocate(layout).is_err());
+        }
+        #[cfg(feature = "pool")]
+        {
+            let alloqer = crate::pool::Alloq::<Isr>::new(heap.as_mut_ptr_range());
+            let _guard = alloqer.pooler.lock();
+            assert!(alloqer.allocate(layout).is_err());
+        }
+        #[cfg(all(feature = "cache", feature = "list"))]
+        {
+            type Cache = crate::cache::Alloq<
+                crate::list::first::Alloq,
+                crate::cache::SingleCore,
+                1,
+                32,
+                Isr,
+            >;
+            let alloqer = Cache::new(heap.as_mut_ptr_range());
+            let _guard = alloqer.cores[0].lock();
+            assert!(alloqer.allocate(layout).is_err());
+        }
+    }
+
+    /// A deallocation from an interrupt while the same core is inside the allocator can't fail,
+    /// so it panics instead of deadlocking.
+    #[test]
+    #[cfg(feature = "list")]
+    #[should_panic(expected = "deadlock: lock re-entered")]
+    fn reentrant_deallocation_panics() {
+        use crate::Alloqator;
+        use core::alloc::{Allocator, Layout};
+
+        let mut heap = [0u8; 1024 * 4];
+        let layout = Layout::new::<u64>();
+        let alloqer =
+            crate::list::Alloq::<crate::list::FirstFit, Isr>::new(heap.as_mut_ptr_range());
+        let block = alloqer.allocate(layout).unwrap();
+        let _guard = alloqer.first.lock();
+        unsafe { alloqer.deallocate(block.cast(), layout) };
+    }
+}
@@ -0,0 +1,308 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{alloc::Layout, fmt, ops::Range, ptr::NonNull};
+
+use crate::Alloqator;
+
+/// Live blocks the model keeps at most; past it, allocations become frees.
+pub const MAX_LIVE: usize = 64;
+/// Sizes are below this, or below 64 half of the time.
+pub const MAX_SIZE: usize = 2048;
+
+/// An operation decoded from the input. Indexes are taken modulo the live blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Alloc(Layout),
+    AllocZeroed(Layout),
+    Free(usize),
+    Grow(usize, usize),
+    GrowZeroed(usize, usize),
+    Shrink(usize, usize),
+}
+
+/// Reads `Op`s from arbitrary bytes, so a fuzzer's input or a seeded generator's can drive
+/// `check`. It stops when the bytes run out.
+pub struct Ops<'a>(&'a [u8]);
+
+impl<'a> Ops<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(byte)
+    }
+
+    fn size(&mut self) -> Option<usize> {
+        let bits = u16::from_le_bytes([self.byte()?, self.byte()?]) as usize;
+        Some(if bits & 1 == 0 {
+            (bits >> 1) % 64
+        } else {
+            (bits >> 1) % MAX_SIZE
+        })
+    }
+
+    fn layout(&mut self) -> Option<Layout> {
+        let size = self.size()?;
+        // Mostly up to 128, sometimes up to 4096.
+        let align = match self.byte()? {
+            bits @ 0..=223 => 1 << (bits % 8),
+            bits => 1 << (bits % 13),
+        };
+        Layout::from_size_align(size, align).ok()
+    }
+}
+
+impl Iterator for Ops<'_> {
+    type Item = Op;
+
+    fn next(&mut self) -> Option<Op> {
+        Some(match self.byte()? % 8 {
+            0 | 1 => Op::Alloc(self.layout()?),
+            2 => Op::AllocZeroed(self.layout()?),
+            3 | 4 => Op::Free(self.byte()? as usize),
+            5 => Op::Grow(self.byte()? as usize, self.size()? + 1),
+            6 => Op::GrowZeroed(self.byte()? as usize, self.size()? + 1),
+            _ => Op::Shrink(self.byte()? as usize, self.size()?),
+        })
+    }
+}
+
+/// `len` bytes from `seed` with xorshift, for `check`.
+pub fn seeded_bytes(seed: u64, len: usize) -> Vec<u8> {
+    // xorshift never leaves 0.
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+/// What `check` found wrong, at its `step`th operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub step: usize,
+    pub op: Op,
+    pub kind: ViolationKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The block is shorter than its layout's size.
+    TooShort,
+    Misaligned,
+    /// Some of the block is outside of the heap.
+    OutOfBounds,
+    /// The block overlaps a live one.
+    Overlap,
+    /// A block lost its contents, or a neighbour's write changed them.
+    Corrupted,
+    /// A zeroed block or its grown part isn't zeroed.
+    NotZeroed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} at step {}: {:?}", self.kind, self.step, self.op)
+    }
+}
+
+/// What `check` did, to tell a run that tested something from one that only failed to allocate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub steps: usize,
+    /// Successful allocations, grows and shrinks.
+    pub succeeded: usize,
+    /// Failed ones, which are allowed.
+    pub failed: usize,
+}
+
+/// A live block of the shadow model, filled with `fill`.
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Block {
+    fn range(&self) -> Range<usize> {
+        let start = self.ptr.as_ptr() as usize;
+        start..start + self.layout.size()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+
+    fn intact(&self) -> bool {
+        self.bytes().iter().all(|&b| b == self.fill)
+    }
+}
+
+struct Model<'a, A: Alloqator> {
+    alloqer: &'a A,
+    bounds: Option<Range<usize>>,
+    live: Vec<Block>,
+    /// The `fill` of the last block.
+    fill: u8,
+    summary: Summary,
+}
+
+impl<A: Alloqator> Model<'_, A> {
+    fn run(&mut self, op: Op) -> Result<(), Violation> {
+        let step = self.summary.steps;
+        self.step(op).map_err(|kind| Violation { step, op, kind })
+    }
+
+    fn step(&mut self, op: Op) -> Result<(), ViolationKind> {
+        self.summary.steps += 1;
+        match op {
+            Op::Alloc(layout) | Op::AllocZeroed(layout) if self.live.len() < MAX_LIVE => {
+                let zeroed = matches!(op, Op::AllocZeroed(_));
+                let block = if zeroed {
+                    self.alloqer.allocate_zeroed(layout)
+                } else {
+                    self.alloqer.allocate(layout)
+                };
+                match block {
+                    Ok(block) => self.accept(block, layout, None, zeroed),
+                    Err(_) => {
+                        self.summary.failed += 1;
+                        Ok(())
+                    }
+                }
+            }
+            _ if self.live.is_empty() => Ok(()),
+            Op::Alloc(_) | Op::AllocZeroed(_) => self.free(0),
+            Op::Free(i) => self.free(i % self.live.len()),
+            Op::Grow(i, size) | Op::GrowZeroed(i, size) | Op::Shrink(i, size) => {
+                let i = i % self.live.len();
+                let old = &self.live[i];
+                if !old.intact() {
+                    return Err(ViolationKind::Corrupted);
+                }
+                let (size, zeroed) = match op {
+                    Op::Shrink(..) => (size % (old.layout.size() + 1), false),
+                    _ => (old.layout.size() + size, matches!(op, Op::GrowZeroed(..))),
+                };
+                let layout = Layout::from_size_align(size, old.layout.align()).unwrap();
+                let block = unsafe {
+                    match op {
+                        Op::Grow(..) => self.alloqer.grow(old.ptr, old.layout, layout),
+                        Op::GrowZeroed(..) => self.alloqer.grow_zeroed(old.ptr, old.layout, layout),
+                        _ => self.alloqer.shrink(old.ptr, old.layout, layout),
+                    }
+                };
+                match block {
+                    Ok(block) => {
+                        // The new block can be where the old one was, so it doesn't overlap it.
+                        let old = self.live.swap_remove(i);
+                        self.accept(block, layout, Some(old), zeroed)
+                    }
+                    Err(_) if old.intact() => {
+                        self.summary.failed += 1;
+                        Ok(())
+                    }
+                    Err(_) => Err(ViolationKind::Corrupted),
+                }
+            }
+        }
+    }
+
+    fn free(&mut self, i: usize) -> Result<(), ViolationKind> {
+        let block = self.live.swap_remove(i);
+        if !block.intact() {
+            return Err(ViolationKind::Corrupted);
+        }
+        unsafe { self.alloqer.deallocate(block.ptr, block.layout) };
+        Ok(())
+    }
+
+    /// Checks a new block for `layout`, which keeps the contents of `old` if it was reallocated,
+    /// and adds it to the model.
+    fn accept(
+        &mut self,
+        block: NonNull<[u8]>,
+        layout: Layout,
+        old: Option<Block>,
+        zeroed: bool,
+    ) -> Result<(), ViolationKind> {
+        let start = block.cast::<u8>().as_ptr() as usize;
+        if block.len() < layout.size() {
+            return Err(ViolationKind::TooShort);
+        }
+        if !start.is_multiple_of(layout.align()) {
+            return Err(ViolationKind::Misaligned);
+        }
+        self.fill = self.fill.wrapping_add(1);
+        let new = Block {
+            ptr: block.cast(),
+            layout,
+            fill: self.fill,
+        };
+        self.summary.succeeded += 1;
+        if layout.size() == 0 {
+            self.live.push(new);
+            return Ok(());
+        }
+        if let Some(bounds) = &self.bounds {
+            if start < bounds.start || start + block.len() > bounds.end {
+                return Err(ViolationKind::OutOfBounds);
+            }
+        }
+        let range = new.range();
+        if self
+            .live
+            .iter()
+            .any(|other| other.range().start < range.end && range.start < other.range().end)
+        {
+            return Err(ViolationKind::Overlap);
+        }
+        let kept = old
+            .as_ref()
+            .map_or(0, |old| old.layout.size().min(layout.size()));
+        let bytes = new.bytes();
+        if old.is_some_and(|old| bytes[..kept].iter().any(|&b| b != old.fill)) {
+            return Err(ViolationKind::Corrupted);
+        }
+        if zeroed && bytes[kept..].iter().any(|&b| b != 0) {
+            return Err(ViolationKind::NotZeroed);
+        }
+        unsafe { new.ptr.write_bytes(new.fill, layout.size()) };
+        self.live.push(new);
+        Ok(())
+    }
+}
+
+/// Runs `ops` on `alloqer`, checking every block it returns against a model of the live ones:
+/// long enough, aligned, in the heap, not overlapping another and keeping its contents through
+/// grows and shrinks. Failed allocations are allowed, as the heap can run out. The blocks still
+/// live at the end are freed, as `Op::Free(0)`s after the last step. Allocators that need no
+/// heap, like `system`, aren't checked to be in it.
+pub fn check<A: Alloqator>(
+    alloqer: &A,
+    ops: impl IntoIterator<Item = Op>,
+) -> Result<Summary, Violation> {
+    let mut model = Model {
+        alloqer,
+        bounds: (A::required_heap_size(&[Layout::new::<u64>()]) != Some(0))
+            .then(|| alloqer.heap_start() as usize..alloqer.heap_end() as usize),
+        live: Vec::with_capacity(MAX_LIVE),
+        fill: 0,
+        summary: Summary::default(),
+    };
+    for op in ops {
+        model.run(op)?;
+    }
+    while !model.live.is_empty() {
+        model.run(Op::Free(0))?;
+    }
+    Ok(model.summary)
+}
@@ -1,23 +1,43 @@
 use core::{
-    alloc::{AllocError, Allocator},
+    alloc::{AllocError, Allocator, Layout},
     mem,
     ops::Range,
     ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::Alloqator;
-use spin::Mutex;
+use crate::{
+    fragmentation::{HeapWalk, Region},
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
 
 pub const DEFAULT_CHUNK_SIZE: usize = 64;
 pub const DEFAULT_ALIGNMENT: usize = 2;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct RawChunk {
     pub addr: *mut u8,
     pub chunk: *const u8,
     /// When freed, they point to the next free block. When allocated, to the continuous part.
     pub next: *mut Self,
     pub back: *mut Self,
+    /// Index (plus one) of the next chunk in the lock-free free stack, see `Alloq::push_free`.
+    /// It's only accessed atomically, since a stale `Alloq::pop_free` can still read it after
+    /// the chunk was taken by another thread.
+    pub free_next: AtomicUsize,
+}
+
+impl Clone for RawChunk {
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr,
+            chunk: self.chunk,
+            next: self.next,
+            back: self.back,
+            free_next: AtomicUsize::new(self.free_next.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl RawChunk {
@@ -29,6 +49,7 @@ impl RawChunk {
             chunk: bind,
             next: null_mut(),
             back: null_mut(),
+            free_next: AtomicUsize::new(0),
         }
     }
 
@@ -135,30 +156,33 @@ impl RawChunk {
         // std::println!("{:?}", null_mut::<Self>());
     }
 
+    /// Sorts the list ending at `self` by address, returning its new end. It's a bottom-up merge
+    /// sort: `bins[i]` holds a sorted list of `2^i` chunks, so it's O(n log n) without recursing,
+    /// even for heaps with millions of chunks.
     pub fn sort(&mut self) -> &mut Self {
-        // TODO: To avoid a `first` call, merge reverse.
-        // Divide it into a tree:
-        // e c b d a == MERGE(e, c, MERGE(b, d, MERGE(a, -, -)))
-        // e c
-        //     b d
-        //         a
-        let mut it = self.back_iter();
-        let r = it.next().unwrap();
-        if let Some(l) = it.next() {
+        let mut bins = [null_mut::<Self>(); usize::BITS as usize];
+        for node in self.back_iter() {
             unsafe {
-                (*r).disconnect();
-                (*l).disconnect();
-                let merged = Self::merge(&mut *l, &mut *r);
-                if let Some(n) = it.next() {
-                    let f = (*n).sort();
-                    Self::merge(merged, f)
-                } else {
-                    merged
+                (*node).disconnect();
+                let mut carry = &mut *node;
+                let mut i = 0;
+                while !bins[i].is_null() {
+                    carry = Self::merge(&mut *bins[i], carry);
+                    bins[i] = null_mut();
+                    i += 1;
                 }
+                bins[i] = carry;
             }
-        } else {
-            unsafe { &mut *r }
         }
+        let mut sorted: *mut Self = null_mut();
+        for bin in bins.into_iter().filter(|bin| !bin.is_null()) {
+            sorted = if sorted.is_null() {
+                bin
+            } else {
+                unsafe { Self::merge(&mut *bin, &mut *sorted) }
+            };
+        }
+        unsafe { &mut *sorted }
     }
 
     // Internal mutation
@@ -246,13 +270,21 @@ impl Iterator for RawChunkBackIter {
 
 /// An fixed-size allocator with a pool memory managment. Using a native reverse link-list, its map
 /// the block and use two lists to cache them.
+/// Single-chunk blocks are freed to a lock-free stack (a Treiber stack with a tagged head), so the
+/// common case of allocating and deallocating one chunk never takes `pooler`. The locked lists
+/// are only used to map new chunks and to find continuous chains for bigger layouts.
 #[derive(Debug)]
-pub struct Alloq {
+pub struct Alloq<L: RawLock = Spin> {
     heap_start: *mut u8,
     heap_end: *mut u8,
     chunk_size: usize,
     align: usize,
-    pooler: Mutex<Pool>,
+    pub pooler: Mutex<L, Pool>,
+    /// Head of the lock-free free stack. The low `index_bits` bits store the index (plus one, 0
+    /// being an empty stack) of the top chunk and the high bits store a tag, incremented on every
+    /// update to avoid ABA.
+    free_stack: AtomicUsize,
+    index_bits: u32,
 }
 
 #[derive(Debug)]
@@ -262,6 +294,18 @@ pub struct Pool {
 }
 
 impl Pool {
+    /// Returns if there's room to map one more chunk (and its metadata).
+    pub fn can_map(&self, chunk_size: usize) -> bool {
+        unsafe {
+            let addr = (*self.list_end).chunk.wrapping_add(chunk_size);
+            let meta = crate::align_down(
+                self.list_end.wrapping_sub(1) as usize,
+                mem::align_of::<RawChunk>(),
+            );
+            (addr.wrapping_add(chunk_size) as usize) < meta
+        }
+    }
+
     pub fn get_free_chunk(&mut self, chunk_size: usize, align: usize) -> *mut RawChunk {
         let last = unsafe { &mut *self.free_last };
         let freed = if last.back.is_null() {
@@ -270,6 +314,7 @@ impl Pool {
             unsafe { &mut *last.back }
         };
         freed.disconnect();
+        freed.addr = crate::align_up(freed.chunk as usize, align) as *mut u8;
         freed
     }
 
@@ -277,12 +322,16 @@ impl Pool {
     /// `ptr` must be previous returned by `Alloq::allocate`.
     pub unsafe fn remove_used(&mut self, raw_chunk_ptr: *mut RawChunk) {
         let raw_chunk = &mut *raw_chunk_ptr;
-        let last = raw_chunk.back; // TODO: Optimise and use `back` from the
+        // `back` only points to the chain's end when it was freshly mapped, so walk it.
+        let last = raw_chunk.last().cast_mut();
         RawChunk::connect_unchecked(&mut *self.free_last, raw_chunk);
-        self.free_last = if last.is_null() { raw_chunk_ptr } else { last };
+        self.free_last = last;
     }
 
-    /// Get a `RawChunk` chain that can allocate the `layout`
+    /// Get a `RawChunk` chain that can allocate the `layout`, or null if there's no room to map
+    /// it. Chunks already mapped stay in the free list. The chain always starts at the chunk
+    /// holding the aligned address, so layouts aligned beyond `chunk_size` skip the chunks before
+    /// it.
     /// # Safety
     /// The `free` must be ordered and `layout.size` must need more than one chunk
     pub unsafe fn get_free_chunk_chain_ordered(
@@ -299,7 +348,8 @@ impl Pool {
             (*back).chunk.offset_from((*next).chunk) == chunk_size as isize
         };
         // TODO: use `chunk_size` for optimisation reasons
-        for c in (*self.free_last).back_iter() {
+        // `free_last` is the list's tail and can't be handed out.
+        for c in (*self.free_last).back_iter().skip(1) {
             if last.is_null() {
                 last = c;
                 start = c;
@@ -312,8 +362,15 @@ impl Pool {
                 start = null_mut();
                 continue;
             }
-            if (*last).chunk.offset_from(aligned) >= needed as isize {
+            // Over-aligned layouts may land in a later chunk, so keep going down until `start`
+            // holds it.
+            if aligned < (*start).chunk.add(chunk_size).cast_mut()
+                && (*last).chunk.offset_from(aligned) >= needed as isize
+            {
                 if last == self.list_end {
+                    if !self.can_map(chunk_size) {
+                        return null_mut();
+                    }
                     self.free_last = (*self.free_last).alloc_next(
                         &mut self.list_end,
                         chunk_size,
@@ -325,14 +382,32 @@ impl Pool {
                 return start;
             }
         }
+        if !self.can_map(chunk_size) {
+            return null_mut();
+        }
         self.free_last =
             (*self.free_last).alloc_next(&mut self.list_end, chunk_size, layout.align());
         start = self.free_last;
         aligned = crate::align_up((*start).chunk as usize, layout.align()) as *mut u8;
+        // Mapped chunks before the aligned address stay free.
+        while aligned >= (*start).chunk.add(chunk_size).cast_mut() {
+            if !self.can_map(chunk_size) {
+                return null_mut();
+            }
+            self.free_last =
+                (*self.free_last).alloc_next(&mut self.list_end, chunk_size, layout.align());
+            start = self.free_last;
+        }
         while (*self.free_last).chunk.offset_from(aligned) < needed as isize {
+            if !self.can_map(chunk_size) {
+                return null_mut();
+            }
             self.free_last =
                 (*self.free_last).alloc_next(&mut self.list_end, chunk_size, layout.align());
         }
+        if !self.can_map(chunk_size) {
+            return null_mut();
+        }
         last = self.free_last;
         self.free_last =
             (*self.free_last).alloc_next(&mut self.list_end, chunk_size, layout.align());
@@ -343,7 +418,243 @@ impl Pool {
     }
 }
 
-impl Alloq {
+/// Most runs of free chunks a `Plan` tracks. Past that, `Alloq::required_heap_size_with` can't
+/// plan the heap.
+pub const PLAN_RUNS: usize = 64;
+
+/// Replays `Alloq::allocate` over chunk indices, without deallocations, to know how many chunks
+/// get mapped. Chunk `i` starts `i * chunk_size` bytes after an aligned heap start.
+struct Plan {
+    chunk_size: usize,
+    mapped: usize,
+    /// The free list, as sorted runs of chunks `(first, last)` that aren't adjacent. Its last
+    /// chunk is `free_last`.
+    runs: [(usize, usize); PLAN_RUNS],
+    len: usize,
+    /// A run didn't fit in `runs`, so the plan is wrong.
+    overflowed: bool,
+}
+
+impl Plan {
+    /// The first chunk is mapped by `Alloq::with_chunk_size`, as `free_last`.
+    const fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size,
+            mapped: 1,
+            runs: [(0, 0); PLAN_RUNS],
+            len: 1,
+            overflowed: false,
+        }
+    }
+
+    /// Maps a chunk after `free_last`, like `RawChunk::alloc_next`.
+    const fn map_free(&mut self) -> usize {
+        let chunk = self.mapped;
+        self.mapped += 1;
+        self.insert(chunk);
+        chunk
+    }
+
+    /// The chunk before `free_last`, which `Pool::get_free_chunk` takes.
+    const fn before_last(&self) -> Option<usize> {
+        let (first, last) = self.runs[self.len - 1];
+        if first < last {
+            Some(last - 1)
+        } else if self.len > 1 {
+            Some(self.runs[self.len - 2].1)
+        } else {
+            None
+        }
+    }
+
+    const fn insert(&mut self, chunk: usize) {
+        let mut i = 0;
+        while i < self.len && self.runs[i].1 < chunk {
+            i += 1;
+        }
+        let joins_back = i > 0 && self.runs[i - 1].1 + 1 == chunk;
+        let joins_next = i < self.len && self.runs[i].0 == chunk + 1;
+        match (joins_back, joins_next) {
+            (true, true) => {
+                self.runs[i - 1].1 = self.runs[i].1;
+                self.delete_run(i);
+            }
+            (true, false) => self.runs[i - 1].1 = chunk,
+            (false, true) => self.runs[i].0 = chunk,
+            (false, false) => self.insert_run(i, (chunk, chunk)),
+        }
+    }
+
+    /// Removes `first..=last`, which must be in a single run.
+    const fn remove(&mut self, first: usize, last: usize) {
+        let mut i = 0;
+        while self.runs[i].1 < first {
+            i += 1;
+        }
+        let run = self.runs[i];
+        if run.0 == first && run.1 == last {
+            self.delete_run(i);
+        } else if run.0 == first {
+            self.runs[i].0 = last + 1;
+        } else if run.1 == last {
+            self.runs[i].1 = first - 1;
+        } else {
+            self.runs[i].1 = first - 1;
+            self.insert_run(i + 1, (last + 1, run.1));
+        }
+    }
+
+    const fn insert_run(&mut self, i: usize, run: (usize, usize)) {
+        if self.len == PLAN_RUNS {
+            self.overflowed = true;
+            return;
+        }
+        let mut j = self.len;
+        while j > i {
+            self.runs[j] = self.runs[j - 1];
+            j -= 1;
+        }
+        self.runs[i] = run;
+        self.len += 1;
+    }
+
+    const fn delete_run(&mut self, i: usize) {
+        let mut j = i;
+        while j + 1 < self.len {
+            self.runs[j] = self.runs[j + 1];
+            j += 1;
+        }
+        self.len -= 1;
+    }
+
+    /// Like `Pool::get_free_chunk_chain_ordered` searching the free list.
+    const fn take_chain(&mut self, layout: Layout) -> bool {
+        let chunk_size = self.chunk_size;
+        let (mut start, mut last, mut open) = (0, 0, false);
+        let mut is_free_last = true;
+        let mut i = self.len;
+        while i > 0 {
+            i -= 1;
+            let (first, run_last) = self.runs[i];
+            let mut c = run_last + 1;
+            while c > first {
+                c -= 1;
+                if is_free_last {
+                    is_free_last = false;
+                    continue;
+                }
+                if !open {
+                    (open, start, last) = (true, c, c);
+                } else if c + 1 == start {
+                    start = c;
+                } else {
+                    open = false;
+                    continue;
+                }
+                let aligned = crate::align_up(start * chunk_size, layout.align());
+                if aligned < (start + 1) * chunk_size
+                    && last * chunk_size >= aligned + layout.size()
+                {
+                    self.remove(start, last);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns `false` if it would overflow.
+    const fn allocate(&mut self, layout: Layout) -> bool {
+        let chunk_size = self.chunk_size;
+        let chunk = match self.before_last() {
+            Some(chunk) => {
+                self.remove(chunk, chunk);
+                chunk
+            }
+            None => {
+                self.mapped += 1;
+                self.mapped - 1
+            }
+        };
+        let end =
+            match crate::align_up(chunk * chunk_size, layout.align()).checked_add(layout.size()) {
+                Some(end) if end < isize::MAX as usize => end,
+                _ => return false,
+            };
+        if end <= (chunk + 1) * chunk_size {
+            return true;
+        }
+        self.insert(chunk);
+        if self.take_chain(layout) {
+            return true;
+        }
+        let mut start = self.map_free();
+        let aligned = crate::align_up(start * chunk_size, layout.align());
+        if aligned.checked_add(layout.size()).is_none()
+            || aligned + layout.size() >= isize::MAX as usize
+        {
+            return false;
+        }
+        while aligned >= (start + 1) * chunk_size {
+            start = self.map_free();
+        }
+        while (self.mapped - 1) * chunk_size < aligned + layout.size() {
+            self.map_free();
+        }
+        let last = self.mapped - 1;
+        self.map_free();
+        self.remove(start, last);
+        true
+    }
+}
+
+impl<L: RawLock> Alloq<L> {
+    /// `Alloqator::required_heap_size`, but `const`, with the default chunk size.
+    pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_heap_size_with(layouts, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// `Alloqator::required_affixed_heap_size`, but `const`, with the default chunk size.
+    pub const fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        Self::plan_heap_size(layouts, DEFAULT_CHUNK_SIZE, affixes)
+    }
+
+    /// The smallest heap for `Alloq::with_chunk_size`, like `Alloqator::required_heap_size`.
+    /// Chunks are mapped from the start and their `RawChunk`s from the end, until they meet.
+    /// It's `None` if the layouts leave more than `PLAN_RUNS` runs of free chunks at once.
+    pub const fn required_heap_size_with(layouts: &[Layout], chunk_size: usize) -> Option<usize> {
+        Self::plan_heap_size(layouts, chunk_size, Affixes::NONE)
+    }
+
+    const fn plan_heap_size(
+        layouts: &[Layout],
+        chunk_size: usize,
+        affixes: Affixes,
+    ) -> Option<usize> {
+        const RAW: usize = mem::size_of::<RawChunk>();
+        if chunk_size <= RAW {
+            return None;
+        }
+        let mut plan = Plan::new(chunk_size);
+        let mut i = 0;
+        while i < layouts.len() {
+            let layout = match affixes.plan(layouts[i]) {
+                Some(layout) => layout,
+                None => return None,
+            };
+            i += 1;
+            if layout.size() != 0 && (!plan.allocate(layout) || plan.overflowed) {
+                return None;
+            }
+        }
+        // `Pool::can_map`: the last chunk must end before its `RawChunk`.
+        let chunks = match (plan.mapped * chunk_size).checked_add((plan.mapped - 1) * RAW) {
+            Some(chunks) if chunks < isize::MAX as usize => chunks,
+            _ => return None,
+        };
+        Some(crate::align_up(chunks + 1, mem::align_of::<RawChunk>()) + RAW)
+    }
+
     /// # Safety
     /// `heap_range` must be a valid heap block.
     pub unsafe fn with_chunk_size(
@@ -362,6 +673,7 @@ impl Alloq {
             mem::size_of::<RawChunk>()
         );
         let free_last = RawChunk::new(heap_range.start, align).allocate(&mut end, chunk_size);
+        let max_chunks = heap_range.end.offset_from(heap_range.start) as usize / chunk_size;
         Self {
             heap_start: heap_range.start,
             heap_end: heap_range.end,
@@ -372,6 +684,124 @@ impl Alloq {
                 list_end: end,
             }
             .into(),
+            free_stack: AtomicUsize::new(0),
+            index_bits: usize::BITS - (max_chunks + 1).leading_zeros(),
+        }
+    }
+
+    /// Splits a free stack head into (tag, index plus one).
+    #[inline(always)]
+    pub fn unpack(&self, head: usize) -> (usize, usize) {
+        (head >> self.index_bits, head & ((1 << self.index_bits) - 1))
+    }
+
+    /// Builds the next free stack head, bumping `tag`.
+    #[inline(always)]
+    pub fn pack(&self, tag: usize, idx: usize) -> usize {
+        (tag.wrapping_add(1) << self.index_bits) | idx
+    }
+
+    #[inline(always)]
+    fn first_raw(&self) -> *mut RawChunk {
+        crate::align_down(
+            self.heap_end() as usize - mem::size_of::<RawChunk>(),
+            mem::align_of::<RawChunk>(),
+        ) as *mut RawChunk
+    }
+
+    /// # Safety
+    /// `idx` must be an already mapped chunk.
+    #[inline(always)]
+    pub unsafe fn raw_chunk_at(&self, idx: usize) -> *mut RawChunk {
+        self.first_raw().sub(idx)
+    }
+
+    /// # Safety
+    /// `raw` must be an already mapped chunk.
+    #[inline(always)]
+    pub unsafe fn raw_chunk_idx(&self, raw: *const RawChunk) -> usize {
+        self.first_raw().cast_const().offset_from(raw) as usize
+    }
+
+    /// Pushes a single chunk to the lock-free free stack.
+    /// # Safety
+    /// `raw` must be an allocated single chunk (not part of any list).
+    pub unsafe fn push_free(&self, raw: *mut RawChunk) {
+        (*raw).back = null_mut();
+        let idx = self.raw_chunk_idx(raw) + 1;
+        let mut head = self.free_stack.load(Ordering::Relaxed);
+        loop {
+            let (tag, next) = self.unpack(head);
+            (*raw).free_next.store(next, Ordering::Relaxed);
+            match self.free_stack.compare_exchange_weak(
+                head,
+                self.pack(tag, idx),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Pops a single chunk from the lock-free free stack.
+    pub fn pop_free(&self) -> Option<*mut RawChunk> {
+        let mut head = self.free_stack.load(Ordering::Acquire);
+        loop {
+            let (tag, idx) = self.unpack(head);
+            if idx == 0 {
+                return None;
+            }
+            // SOUND: only mapped chunks are pushed and `free_next` is atomic, so a stale read is
+            // harmless: the tag makes the exchange fail.
+            let raw = unsafe { self.raw_chunk_at(idx - 1) };
+            let next = unsafe { (*raw).free_next.load(Ordering::Relaxed) };
+            match self.free_stack.compare_exchange_weak(
+                head,
+                self.pack(tag, next),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(raw),
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Takes a chunk from the lock-free free stack if it can hold `layout` alone.
+    pub fn pop_free_fitting(&self, layout: core::alloc::Layout) -> Option<*mut RawChunk> {
+        if layout.size() > self.chunk_size {
+            return None;
+        }
+        let raw = self.pop_free()?;
+        unsafe {
+            let addr = crate::align_up((*raw).chunk as usize, layout.align()) as *mut u8;
+            if addr.add(layout.size()) > (*raw).chunk.add(self.chunk_size).cast_mut() {
+                self.push_free(raw);
+                return None;
+            }
+            (*raw).addr = addr;
+        }
+        Some(raw)
+    }
+
+    /// Moves every chunk of the lock-free free stack to the locked free list, so they can be
+    /// used by `Pool::get_free_chunk_chain_ordered`.
+    pub fn drain_free_stack(&self, pooler: &mut Pool) {
+        let head = self
+            .free_stack
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |head| {
+                Some(self.pack(self.unpack(head).0, 0))
+            })
+            .unwrap();
+        let mut idx = self.unpack(head).1;
+        while idx != 0 {
+            unsafe {
+                let raw = self.raw_chunk_at(idx - 1);
+                idx = (*raw).free_next.load(Ordering::Relaxed);
+                pooler.remove_used(raw);
+            }
         }
     }
 
@@ -383,16 +813,10 @@ impl Alloq {
         ptr: *const u8,
         _layout: core::alloc::Layout,
     ) -> *const RawChunk {
-        // SOUND: `ptr` is always `crate::align_up`, which garantees `*ptr` > `*chunk`
-        let chunk = crate::align_down(ptr as usize, self.align);
-        let first_chunk = crate::align_up(self.heap_start() as usize, self.align);
-        let chunk_idx = (chunk - first_chunk) / self.chunk_size;
-        let first_raw = crate::align_down(
-            self.heap_end().sub(mem::size_of::<RawChunk>()) as usize,
-            mem::align_of::<RawChunk>(),
-        ) as *const RawChunk;
-        // FIXME: Is size always multiple of alignment?
-        let raw = first_raw.offset(-(chunk_idx as isize));
+        // SOUND: chunks are mapped every `chunk_size` bytes from `heap_start`, and `ptr` is
+        // always inside the first chunk of its chain, whatever its alignment.
+        let chunk_idx = (ptr as usize - self.heap_start() as usize) / self.chunk_size;
+        let raw = self.raw_chunk_at(chunk_idx).cast_const();
         debug_assert_eq!(
             ptr,
             (*raw).addr,
@@ -402,23 +826,39 @@ impl Alloq {
     }
 }
 
-unsafe impl Allocator for Alloq {
+unsafe impl<L: RawLock> Allocator for Alloq<L> {
     /// Pass pre-allocated block and add its to the used list. If there's no available blocks, map
-    /// one.
+    /// one. Single-chunk layouts are first taken from the lock-free free stack.
     fn allocate(&self, layout: core::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let chunk = {
-            {
-                let mut pooler = self.pooler.lock();
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
+        }
+        if layout.size() > self.heap_end as usize - self.heap_start as usize {
+            return Err(AllocError);
+        }
+        let chunk = match self.pop_free_fitting(layout) {
+            Some(chunk) => chunk,
+            None => {
+                let mut pooler = self.pooler.acquire()?;
+                if unsafe { (*pooler.free_last).back.is_null() } && !pooler.can_map(self.chunk_size)
+                {
+                    return Err(AllocError);
+                }
                 let chunk = pooler.get_free_chunk(self.chunk_size, layout.align());
                 if unsafe {
                     (*chunk).addr.add(layout.size())
                         > (*chunk).chunk.add(self.chunk_size).cast_mut()
                 } {
-                    unsafe {
+                    let chain = unsafe {
                         pooler.remove_used(chunk);
+                        self.drain_free_stack(&mut pooler);
                         pooler.free_last = (*pooler.free_last).sort();
                         pooler.get_free_chunk_chain_ordered(self.chunk_size, self.align, layout)
+                    };
+                    if chain.is_null() {
+                        return Err(AllocError);
                     }
+                    chain
                 } else {
                     debug_assert!(
                         self.heap_range()
@@ -458,23 +898,87 @@ unsafe impl Allocator for Alloq {
     }
 
     /// Moves the block to the free list. In these newer versions, deallocating is `O(1)`, as the
-    /// block is on a constant place.
+    /// block is on a constant place. Single chunks go to the lock-free free stack, only chains
+    /// take the lock.
     /// Unsafe:
     /// - It's Undefined Behaviour to double-free a value. It can enter twice in the `used` stack
     ///   and be shared across two objects them.
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: core::alloc::Layout) {
+        if layout.size() == 0 {
+            return;
+        }
         unsafe {
-            let raw_chunk = self.get_raw_chunk_from(ptr.as_ptr(), layout);
-            self.pooler.lock().remove_used(raw_chunk as *mut RawChunk);
+            let raw_chunk = self.get_raw_chunk_from(ptr.as_ptr(), layout) as *mut RawChunk;
+            if (*raw_chunk).next.is_null() {
+                self.push_free(raw_chunk);
+            } else {
+                self.pooler.lock().remove_used(raw_chunk);
+            }
+        }
+    }
+
+    /// In place if it still fits in its chunks.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+        let usable = self.usable_size(ptr, old_layout);
+        if new_layout.size() <= usable && (ptr.as_ptr() as usize).is_multiple_of(new_layout.align())
+        {
+            return Ok(NonNull::slice_from_raw_parts(ptr, usable));
         }
+        crate::move_block(self, ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.grow(ptr, old_layout, new_layout)?;
+        let tail = block.cast::<u8>().add(old_layout.size());
+        tail.write_bytes(0, block.len() - old_layout.size());
+        Ok(block)
+    }
+
+    /// In place, keeping all its chunks, unless `ptr` isn't aligned to `new_layout`.
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(crate::dangling(new_layout));
+        }
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            return crate::move_block(self, ptr, old_layout, new_layout);
+        }
+        let usable = self.usable_size(ptr, old_layout);
+        Ok(NonNull::slice_from_raw_parts(ptr, usable))
     }
 }
 
 // Why rustfmt is removing comments?
 // impl /*Alloqator for*/ Pool {
-impl Alloqator for Alloq {
+impl<L: RawLock> Alloqator for Alloq<L> {
     type Metadata = RawChunk;
 
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, affixes)
+    }
+
     fn new(heap_range: Range<*mut u8>) -> Self {
         unsafe { Self::with_chunk_size(heap_range, DEFAULT_CHUNK_SIZE, DEFAULT_ALIGNMENT) }
     }
@@ -487,6 +991,18 @@ impl Alloqator for Alloq {
         self.heap_end
     }
 
+    /// Until the end of its chunk chain.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if layout.size() == 0 {
+            return 0;
+        }
+        let raw_chunk = self.get_raw_chunk_from(ptr.as_ptr(), layout);
+        (*(*raw_chunk).last())
+            .chunk
+            .add(self.chunk_size)
+            .offset_from(ptr.as_ptr()) as usize
+    }
+
     unsafe fn reset(&self) {
         let mut pooler = self.pooler.lock();
         // SAFE: Its will not be even used as a `RawChunk`.
@@ -496,135 +1012,76 @@ impl Alloqator for Alloq {
         };
         pooler.free_last = free_last;
         pooler.list_end = end;
+        self.free_stack.store(0, Ordering::Release);
     }
 
     // TODO: Improve shrink and grow by simply link another pointer
 }
 
-crate::impl_allocator!(Alloq);
-
-#[cfg(test)]
-pub mod tests {
-    extern crate alloc;
-    extern crate std;
-
-    use alloc::{boxed::Box, vec::Vec};
-
-    use super::Alloq;
-    use crate::Alloqator;
-    use core::{alloc::Layout, mem::MaybeUninit, ptr::null_mut};
-    use std::thread;
-
-    #[test]
-    fn simple_alloc() {
-        let mut heap = [0u8; 512 * 8];
-        let alloqer = Alloq::new(heap.as_mut_ptr_range());
-        let layout = Layout::from_size_align(32, 2).unwrap();
-        let ptr = alloqer.alloq(layout);
-        unsafe { alloqer.dealloq(ptr, layout) };
-    }
-
-    #[test]
-    fn linear_allocs() {
-        let mut heap = [0u8; 512 * 512];
-        let alloqer = Alloq::new(heap.as_mut_ptr_range());
-        let layout = Layout::from_size_align(32, 2).unwrap();
-        let mut chunks_allocated = [null_mut(); 4];
-        for chunk in chunks_allocated.iter_mut() {
-            *chunk = alloqer.alloq(layout);
-        }
-
-        for &mut chunk in chunks_allocated.iter_mut() {
-            unsafe { alloqer.dealloq(chunk, layout) }
-        }
-    }
-
-    #[test]
-    fn stack_allocs() {
-        let mut heap = [0u8; 512 * 512];
-        let alloqer = Alloq::new(heap.as_mut_ptr_range());
-        let layout = Layout::from_size_align(32, 2).unwrap();
-        let mut chunks_allocated = [null_mut(); 4];
-        for chunk in chunks_allocated.iter_mut() {
-            *chunk = alloqer.alloq(layout);
-        }
-
-        for &mut chunk in chunks_allocated.iter_mut().rev() {
-            unsafe { alloqer.dealloq(chunk, layout) }
-        }
-    }
-
-    #[test]
-    fn multithread_allocs() {
-        static mut ALLOQER: MaybeUninit<Alloq> = MaybeUninit::uninit();
-        let mut heap = [0u8; 1024];
+impl<L: RawLock> HeapWalk for Alloq<L> {
+    /// Goes over the mapped chunks with the free list sorted, so it drains the free stack and
+    /// sorts it first, as allocating a chain does. The room left to map more chunks is free, and
+    /// the `RawChunk`s are metadata, like the padding before a block that its alignment (or a
+    /// misaligned `heap_start`) left in its first chunk.
+    fn walk(&self, f: &mut dyn FnMut(Range<*mut u8>, Region)) {
+        let mut pooler = self.pooler.lock();
+        self.drain_free_stack(&mut pooler);
+        let chunk = |idx: usize| self.heap_start.wrapping_add(idx * self.chunk_size);
         unsafe {
-            ALLOQER = MaybeUninit::new(Alloq::new(heap.as_mut_ptr_range()));
-        }
-        let layout = Layout::from_size_align(32, 2).unwrap();
-        let thread = thread::spawn(|| {
-            let layout = Layout::from_size_align(32, 2).unwrap();
-            for _ in 0..100 {
-                let ptr = unsafe { ALLOQER.assume_init_mut().alloq(layout) };
-                unsafe { ALLOQER.assume_init_mut().dealloq(ptr, layout) };
+            pooler.free_last = (*pooler.free_last).sort();
+            let mapped = self.raw_chunk_idx(pooler.list_end) + 1;
+            let mut free = (*pooler.free_last).first();
+            let mut free_run = None;
+            let mut idx = 0;
+            while idx < mapped {
+                let raw = self.raw_chunk_at(idx);
+                if raw.cast_const() == free {
+                    free = (*raw).next;
+                    free_run.get_or_insert(idx);
+                    idx += 1;
+                } else {
+                    if let Some(run) = free_run.take() {
+                        f(chunk(run)..chunk(idx), Region::Free);
+                    }
+                    let len = (*raw).iter().count();
+                    let addr = (*raw).addr;
+                    if chunk(idx) < addr {
+                        f(chunk(idx)..addr, Region::Metadata);
+                    }
+                    f(addr..chunk(idx + len), Region::Used);
+                    idx += len;
+                }
             }
-        });
-        for _ in 0..100 {
-            let ptr = unsafe { ALLOQER.assume_init_mut().alloq(layout) };
-            unsafe { ALLOQER.assume_init_mut().dealloq(ptr, layout) };
+            let meta = pooler.list_end.cast::<u8>();
+            let run = chunk(free_run.unwrap_or(mapped));
+            if run < meta {
+                f(run..meta, Region::Free);
+            }
+            f(meta..self.heap_end, Region::Metadata);
         }
-        thread.join().unwrap();
     }
+}
 
-    #[test]
-    fn vec_grow() {
-        let mut heap_stackish = [0u8; 512];
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        let mut v = Vec::new_in(&alloqer);
-        for x in 0..10 {
-            v.push(x);
-        }
-        assert_eq!(v.iter().sum::<i32>(), 45i32);
-    }
+crate::impl_allocator!(Alloq<L: RawLock>);
 
-    #[test]
-    fn boxed() {
-        let mut heap_stackish = [0u8; 512];
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        let b = Box::new_in(255u8, &alloqer);
-        let c = Box::new_in(127u8, &alloqer);
-        let b_ptr = &*b as *const u8;
-        let c_ptr = &*c as *const u8;
-        assert_ne!(b_ptr, core::ptr::null_mut());
-        assert_ne!(c_ptr, core::ptr::null_mut());
-        assert_ne!(b_ptr, c_ptr);
-    }
+#[cfg(test)]
+pub mod tests {
+    use core::ptr::NonNull;
 
-    #[test]
-    fn fragmented_heap() {
-        let mut heap_stackish = [0u8; 1024 * 1024];
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        let mut v: Vec<u8, _> = Vec::new_in(&alloqer);
-        let mut w: Vec<u8, _> = Vec::new_in(&alloqer);
-        for x in 0..128 {
-            match x % 2 {
-                0 => v.push(x),
-                1 => w.push(x),
-                _ => unreachable!(),
-            }
-        }
-        assert!(v.iter().all(|i| i % 2 == 0));
-        assert!(w.iter().all(|i| i % 2 == 1));
-    }
+    type Alloq = super::Alloq;
+
+    const PLANS_HEAP: bool = true;
+
+    include!("test.template.rs");
 
     #[test]
-    fn custom_structs() {
+    fn custom_structs_large_chunks() {
         struct S {
             _foo: i32,
             _bar: [u16; 8],
             _baz: &'static str,
         }
-        let mut heap_stackish = [0u8; crate::get_size_hint_in::<S, Alloq>(10) * 2];
+        let mut heap_stackish = [0u8; 1024 * 2];
         let alloqer = unsafe { Alloq::with_chunk_size(heap_stackish.as_mut_ptr_range(), 512, 2) };
         let mut v = Vec::with_capacity_in(10, &alloqer);
         for x in 0..10 {
@@ -648,9 +1105,9 @@ pub mod tests {
     }
 
     #[test]
-    fn full_heap() {
+    fn full_heap_large_chunks() {
         const VECTOR_SIZE: usize = 16;
-        let mut heap_stackish = [0u8; crate::get_size_hint_in::<[u16; 32], Alloq>(VECTOR_SIZE) * 2];
+        let mut heap_stackish = [0u8; 1024 * 4];
         let alloqer = unsafe { Alloq::with_chunk_size(heap_stackish.as_mut_ptr_range(), 1024, 2) };
         let mut v = Vec::with_capacity_in(VECTOR_SIZE, &alloqer);
         for x in 0..VECTOR_SIZE {
@@ -659,21 +1116,99 @@ pub mod tests {
         }
     }
 
+    /// Allocates `layouts` in a heap of exactly `size` bytes, starting 4 KiB aligned.
+    fn fits_with(layouts: &[Layout], size: usize, chunk_size: usize) -> bool {
+        const ALIGN: usize = 4096;
+        let mut buffer = alloc::vec![0u8; size + ALIGN];
+        let start = buffer
+            .as_mut_ptr()
+            .map_addr(|addr| crate::align_up(addr, ALIGN));
+        let alloqer =
+            unsafe { Alloq::with_chunk_size(start..start.wrapping_add(size), chunk_size, 8) };
+        layouts
+            .iter()
+            .all(|&layout| !alloqer.alloq(layout).is_null())
+    }
+
     #[test]
-    fn zero_sized() {
-        const VECTOR_SIZE: usize = 1024;
-        // FIXME: 32768 bytes for ZST? Really?
-        let mut heap_stackish = [0u8; crate::get_size_hint_in::<(), Alloq>(VECTOR_SIZE)];
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        let mut v = Vec::with_capacity_in(VECTOR_SIZE, &alloqer);
-        for _ in 0..VECTOR_SIZE {
-            v.push(());
+    fn required_heap_size_chunk_sizes() {
+        let layouts = [
+            Layout::new::<u8>(),
+            Layout::new::<[u64; 20]>(),
+            Layout::new::<()>(),
+            Layout::from_size_align(24, 32).unwrap(),
+            Layout::new::<[u8; 100]>(),
+            Layout::from_size_align(8, 512).unwrap(),
+            Layout::new::<u32>(),
+            Layout::from_size_align(300, 4096).unwrap(),
+            Layout::new::<u64>(),
+            Layout::new::<[u8; 64]>(),
+            Layout::new::<[u16; 5]>(),
+        ];
+        for chunk_size in [64, 100, 256] {
+            for n in 1..=layouts.len() {
+                let layouts = &layouts[..n];
+                let size = Alloq::required_heap_size_with(layouts, chunk_size).unwrap();
+                assert!(
+                    fits_with(layouts, size, chunk_size),
+                    "{size} bytes can't fit them"
+                );
+                assert!(
+                    !fits_with(layouts, size - 1, chunk_size),
+                    "{size} bytes isn't the smallest heap"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn const_heap_size() {
+        const LAYOUTS: [Layout; 2] = [Layout::new::<u64>(), Layout::new::<[u8; 100]>()];
+        static HEAP: crate::heap::Heap<Alloq, { Alloq::required_heap_size(&LAYOUTS).unwrap() }> =
+            crate::heap::Heap::new();
+        assert!(LAYOUTS.iter().all(|&layout| !HEAP.alloq(layout).is_null()));
+    }
+
+    /// Random layouts, so the plan can't drift from `allocate` unnoticed.
+    #[test]
+    fn required_heap_size_random() {
+        for seed in 0..256 {
+            let bytes = crate::model::seeded_bytes(seed, 3 * 24 + 1);
+            let chunk_size = [64, 100, 256][bytes[0] as usize % 3];
+            let layouts: Vec<_> = bytes[1..]
+                .chunks(3)
+                .map(|b| {
+                    let size = 1 + u16::from_le_bytes([b[0], b[1]]) as usize % 600;
+                    Layout::from_size_align(size, 1 << (b[2] % 11)).unwrap()
+                })
+                .collect();
+            let size = Alloq::required_heap_size_with(&layouts, chunk_size)
+                .unwrap_or_else(|| panic!("seed {seed}: not planned"));
+            assert!(
+                fits_with(&layouts, size, chunk_size),
+                "seed {seed}: {size} bytes can't fit them"
+            );
+            assert!(
+                !fits_with(&layouts, size - 1, chunk_size),
+                "seed {seed}: {size} bytes isn't the smallest heap"
+            );
         }
-        assert_eq!(v.len(), VECTOR_SIZE);
     }
 
+    /// Each over-aligned chain leaves the chunks before it free, in a run of their own.
     #[test]
-    fn vector_fragmented() {
+    fn required_heap_size_many_runs() {
+        let layout = Layout::from_size_align(100, 512).unwrap();
+        let layouts = [layout; super::PLAN_RUNS + 8];
+        assert_eq!(Alloq::required_heap_size_with(&layouts, 64), None);
+        let layouts = &layouts[..super::PLAN_RUNS / 2];
+        let size = Alloq::required_heap_size_with(layouts, 64).unwrap();
+        assert!(fits_with(layouts, size, 64));
+        assert!(!fits_with(layouts, size - 1, 64));
+    }
+
+    #[test]
+    fn vector_fragmented_large_chunks() {
         const VECTOR_SIZE: usize = 128;
         let mut heap_stackish = [0u8; 1024 * 1024];
         let alloqer = unsafe { Alloq::with_chunk_size(heap_stackish.as_mut_ptr_range(), 1024, 2) };
@@ -696,30 +1231,6 @@ pub mod tests {
         )
     }
 
-    #[test]
-    fn trash_heap() {
-        let mut heap_stackish: [u8; 1024] = core::array::from_fn(|x| (x % 255) as u8);
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        let mut v = Vec::new_in(&alloqer);
-        for x in 0..10 {
-            v.push(x);
-        }
-        assert_eq!(v.iter().sum::<i32>(), 45i32);
-    }
-
-    #[test]
-    fn corrupted_heap() {
-        let mut heap_stackish: [u8; 1024] = [0; 1024];
-        let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-        heap_stackish.fill(0);
-        unsafe { alloqer.reset() };
-        let mut v = Vec::new_in(&alloqer);
-        for x in 0..10 {
-            v.push(x);
-        }
-        assert_eq!(v.iter().sum::<i32>(), 45i32);
-    }
-
     #[test]
     fn sort() {
         let mut heap_stackish = [0; 1024 * 1024];
@@ -732,6 +1243,7 @@ pub mod tests {
         }
         unsafe {
             let mut lock = alloqer.pooler.lock();
+            alloqer.drain_free_stack(&mut lock);
             (*lock.free_last).sort();
             let mut last = lock.free_last;
             for c in (*lock.free_last).back_iter().skip(1) {
@@ -760,4 +1272,150 @@ pub mod tests {
         let v: Vec<_> = (0..10u128).map(|x| Box::new_in(x, &alloqer)).collect();
         assert!(v.iter().enumerate().all(|(i, x)| **x == i as u128));
     }
+
+    #[test]
+    fn multithread_stress() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 64;
+        const LIVE: usize = 16;
+        let mut heap = [0u8; 1024 * 256];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let small = Layout::new::<[usize; 4]>();
+        let big = Layout::new::<[usize; 32]>();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let alloqer = &alloqer;
+                s.spawn(move || {
+                    for r in 0..ROUNDS {
+                        let id = (t * ROUNDS + r) * LIVE;
+                        let ptrs: Vec<_> = (0..LIVE)
+                            .map(|i| {
+                                let layout = if i % 8 == 0 { big } else { small };
+                                let ptr = alloqer.alloq(layout).cast::<usize>();
+                                unsafe { ptr.write(id + i) };
+                                (ptr, layout)
+                            })
+                            .collect();
+                        for (i, (ptr, layout)) in ptrs.into_iter().enumerate() {
+                            assert_eq!(unsafe { ptr.read() }, id + i, "overlapped allocation");
+                            unsafe { alloqer.dealloq(ptr.cast(), layout) };
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn grow_in_place() {
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let mut v = Vec::with_capacity_in(2, &alloqer);
+        v.extend(0..2u16);
+        let ptr = v.as_ptr();
+        // Still in the same chunk.
+        v.extend(2..16);
+        assert_eq!(v.as_ptr(), ptr);
+        v.truncate(2);
+        v.shrink_to_fit();
+        assert_eq!(v.as_ptr(), ptr);
+        v.extend(2..256);
+        assert_eq!(v.iter().map(|&x| x as u32).sum::<u32>(), (0..256).sum());
+    }
+
+    #[test]
+    fn fragmentation() {
+        use crate::fragmentation::{HeapWalk, Region};
+
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let report = alloqer.fragmentation();
+        assert_eq!((report.free_blocks, report.external()), (1, 0.0));
+        let small = Layout::new::<[u8; 8]>();
+        let large = Layout::new::<[u8; 100]>();
+        let ptrs = [
+            alloqer.alloq(small),
+            alloqer.alloq(large),
+            alloqer.alloq(small),
+            alloqer.alloq(small),
+        ];
+        unsafe {
+            alloqer.dealloq(ptrs[0], small);
+            alloqer.dealloq(ptrs[2], small);
+        }
+        let report = alloqer.fragmentation();
+        assert!(report.free_blocks >= 2, "{report}");
+        assert!(report.external() > 0.0);
+        let used = unsafe {
+            alloqer.usable_size(NonNull::new(ptrs[1]).unwrap(), large)
+                + alloqer.usable_size(NonNull::new(ptrs[3]).unwrap(), small)
+        };
+        assert_eq!(report.used, used);
+        assert_eq!(report.used + report.free + report.metadata, heap.len());
+        let mut next = alloqer.heap_start();
+        alloqer.walk(&mut |range, region| {
+            assert_eq!(range.start, next, "regions must be contiguous");
+            if region == Region::Used {
+                assert!(range.contains(&ptrs[1]) || range.contains(&ptrs[3]));
+            }
+            next = range.end;
+        });
+        assert_eq!(next, alloqer.heap_end());
+        // Still allocates after the free list was sorted.
+        let ptr = alloqer.alloq(large);
+        assert!(!ptr.is_null());
+        unsafe { alloqer.dealloq(ptr, large) };
+    }
+
+    #[test]
+    fn fragmentation_misaligned_heap() {
+        use crate::fragmentation::{HeapWalk, Region};
+
+        let mut heap = [0u8; 1024 * 8];
+        let range = heap.as_mut_ptr_range();
+        let start = range.start.map_addr(|addr| crate::align_up(addr, 64) + 3);
+        let alloqer = Alloq::new(start..range.end);
+        let layouts = [
+            Layout::new::<[u8; 8]>(),
+            Layout::from_size_align(100, 32).unwrap(),
+            Layout::from_size_align(200, 64).unwrap(),
+        ];
+        let ptrs = layouts.map(|layout| alloqer.alloq(layout));
+        let mut next = alloqer.heap_start();
+        let mut used = Vec::new();
+        alloqer.walk(&mut |range, region| {
+            assert_eq!(range.start, next, "regions must be contiguous");
+            if region == Region::Used {
+                used.push(range.clone());
+            }
+            next = range.end;
+        });
+        assert_eq!(next, alloqer.heap_end());
+        // Each used region starts at its block, after the padding.
+        assert_eq!(
+            used.iter().map(|range| range.start).collect::<Vec<_>>(),
+            ptrs
+        );
+        let report = alloqer.fragmentation();
+        let usable: usize = (0..ptrs.len())
+            .map(|i| unsafe { alloqer.usable_size(NonNull::new(ptrs[i]).unwrap(), layouts[i]) })
+            .sum();
+        assert_eq!(report.used, usable);
+        assert_eq!(
+            report.used + report.free + report.metadata,
+            report.heap_size
+        );
+    }
+
+    #[test]
+    fn chunk_slack() {
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let layout = Layout::new::<[u8; 3]>();
+        let block = alloqer.allocate(layout).unwrap();
+        assert_eq!(block.len(), super::DEFAULT_CHUNK_SIZE);
+        let ptr = block.cast::<u8>();
+        assert_eq!(unsafe { alloqer.usable_size(ptr, layout) }, block.len());
+        unsafe { alloqer.deallocate(block.cast(), layout) };
+    }
 }
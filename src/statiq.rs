@@ -1,41 +1,79 @@
 use core::{
-    alloc::{AllocError, Allocator},
+    alloc::{AllocError, Allocator, Layout},
     ptr::NonNull,
 };
 
-use crate::Alloqator;
-
-use spin::Mutex;
+use crate::{
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
 
-pub struct Alloq {
+pub struct Alloq<L: RawLock = Spin> {
     heap_start: *mut u8,
     heap_end: *mut u8,
-    end: Mutex<(/* left */ *const u8, /* right */ *const u8)>,
+    end: Mutex<L, (/* left */ *const u8, /* right */ *const u8)>,
 }
 
-impl Alloq {
-    pub fn r_alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut lock = self.end.lock();
-        let ptr = unsafe { lock.1.offset(-(layout.size() as isize)) };
-        let ptr = crate::align_down(ptr as usize, layout.align()) as *mut u8;
+impl<L: RawLock> Alloq<L> {
+    /// `Alloqator::required_heap_size`, but `const`.
+    pub const fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, Affixes::NONE)
+    }
+
+    /// `Alloqator::required_affixed_heap_size`, but `const`. Blocks go down from the heap's end,
+    /// so it goes backwards: each block needs its size above what the next ones need, aligned.
+    pub const fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        let mut needed = 0usize;
+        let mut i = layouts.len();
+        while i > 0 {
+            i -= 1;
+            let layout = match affixes.plan(layouts[i]) {
+                Some(layout) => layout,
+                None => return None,
+            };
+            if layout.size() == 0 {
+                continue;
+            }
+            needed = match crate::align_up(needed, layout.align()).checked_add(layout.size()) {
+                Some(needed) => needed,
+                None => return None,
+            };
+        }
+        Some(needed)
+    }
+
+    /// Allocates from the right end, going down.
+    pub fn r_alloc(&self, layout: core::alloc::Layout) -> Result<*mut u8, AllocError> {
+        let mut lock = self.end.acquire()?;
+        let ptr = match (lock.1 as usize).checked_sub(layout.size()) {
+            Some(ptr) if crate::align_down(ptr, layout.align()) >= lock.0 as usize => {
+                crate::align_down(ptr, layout.align())
+            }
+            _ => return Err(AllocError),
+        };
+        let ptr = self.heap_start.with_addr(ptr);
         lock.1 = ptr;
-        assert!(ptr as *const u8 >= lock.0, "no available memory");
-        ptr
+        Ok(ptr)
     }
 
-    pub fn l_alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut lock = self.end.lock();
-        let ptr = unsafe { lock.0.offset(-(layout.size() as isize)) };
-        let ptr = crate::align_down(ptr as usize, layout.align()) as *mut u8;
-        lock.0 = ptr;
-        assert!(ptr as *const u8 <= lock.1, "no available memory");
-        ptr
+    /// Allocates from the left end, going up.
+    pub fn l_alloc(&self, layout: core::alloc::Layout) -> Result<*mut u8, AllocError> {
+        let mut lock = self.end.acquire()?;
+        let ptr = crate::align_up(lock.0 as usize, layout.align());
+        match ptr.checked_add(layout.size()) {
+            Some(end) if end <= lock.1 as usize => lock.0 = self.heap_start.with_addr(end),
+            _ => return Err(AllocError),
+        }
+        Ok(self.heap_start.with_addr(ptr))
     }
 }
 
-unsafe impl Allocator for Alloq {
+unsafe impl<L: RawLock> Allocator for Alloq<L> {
     fn allocate(&self, layout: core::alloc::Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let ptr = self.r_alloc(layout);
+        if layout.size() == 0 {
+            return Ok(crate::dangling(layout));
+        }
+        let ptr = self.r_alloc(layout)?;
         let slice = unsafe { core::slice::from_raw_parts_mut(ptr, layout.size()) };
         NonNull::new(slice).ok_or(AllocError)
     }
@@ -43,9 +81,17 @@ unsafe impl Allocator for Alloq {
     unsafe fn deallocate(&self, _: NonNull<u8>, _: core::alloc::Layout) {}
 }
 
-impl Alloqator for Alloq {
+impl<L: RawLock> Alloqator for Alloq<L> {
     type Metadata = ();
 
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        Self::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        Self::required_affixed_heap_size(layouts, affixes)
+    }
+
     fn new(heap_range: core::ops::Range<*mut u8>) -> Self
     where
         Self: Sized,
@@ -72,11 +118,13 @@ impl Alloqator for Alloq {
     }
 }
 
-crate::impl_allocator!(Alloq);
+crate::impl_allocator!(Alloq<L: RawLock>);
 
 #[cfg(test)]
 pub mod tests {
-    use super::Alloq;
+    type Alloq = super::Alloq;
+
+    const PLANS_HEAP: bool = true;
 
     include!("test.template.rs");
 }
@@ -57,6 +57,20 @@ unsafe impl Allocator for Alloq {
 
 impl Alloqator for Alloq {
     type Metadata = ();
+
+    /// It doesn't use the heap.
+    fn required_heap_size(_layouts: &[core::alloc::Layout]) -> Option<usize> {
+        Some(0)
+    }
+
+    /// Nor do affixes.
+    fn required_affixed_heap_size(
+        _layouts: &[core::alloc::Layout],
+        _affixes: crate::Affixes,
+    ) -> Option<usize> {
+        Some(0)
+    }
+
     fn new(_heap_range: core::ops::Range<*mut u8>) -> Self
     where
         Self: Sized,
@@ -66,18 +80,39 @@ impl Alloqator for Alloq {
 
     unsafe fn reset(&self) {}
 
+    /// It has no heap, so its range is empty.
     fn heap_start(&self) -> *mut u8 {
-        panic!("there's no heap start");
+        core::ptr::null_mut()
     }
 
     fn heap_end(&self) -> *mut u8 {
-        panic!("there's no heap end");
+        core::ptr::null_mut()
     }
 }
 
+crate::impl_allocator!(Alloq);
+
 #[cfg(test)]
 pub mod tests {
     use super::Alloq;
 
+    const PLANS_HEAP: bool = true;
+
     include!("test.template.rs");
+
+    #[test]
+    fn global_realloc() {
+        use core::alloc::GlobalAlloc;
+
+        let alloqer = Alloq::new(null_mut()..null_mut());
+        let layout = Layout::array::<u32>(4).unwrap();
+        unsafe {
+            let ptr = alloqer.alloc_zeroed(layout).cast::<u32>();
+            assert!((0..4).all(|i| ptr.add(i).read() == 0));
+            (0..4).for_each(|i| ptr.add(i).write(i as u32));
+            let ptr = alloqer.realloc(ptr.cast(), layout, 1024 * 4).cast::<u32>();
+            assert!((0..4).all(|i| ptr.add(i).read() == i as u32));
+            alloqer.dealloc(ptr.cast(), Layout::array::<u32>(1024).unwrap());
+        }
+    }
 }
@@ -1,8 +1,12 @@
+// Included with `Alloq` and `PLANS_HEAP`, whether `Alloq::required_heap_size` plans its heap.
 extern crate alloc;
 extern crate std;
 use crate::Alloqator;
 use alloc::{boxed::Box, vec::Vec};
-use core::{alloc::Layout, mem::MaybeUninit, ptr::null_mut};
+use core::{
+    alloc::{Allocator, Layout},
+    ptr::null_mut,
+};
 use std::thread;
 
 #[test]
@@ -46,22 +50,19 @@ fn stack_allocs() {
 
 #[test]
 fn multithread_allocs() {
-    static mut ALLOQER: MaybeUninit<Alloq> = MaybeUninit::uninit();
-    let mut heap = [0u8; crate::get_size_hint_in::<i32, Alloq>(200)];
-    unsafe {
-        ALLOQER = MaybeUninit::new(Alloq::new(heap.as_mut_ptr_range()));
-    }
+    static ALLOQER: crate::heap::Heap<Alloq, { 1024 * 16 }> =
+        crate::heap::Heap::new();
     let layout = Layout::new::<i32>();
     let thread = thread::spawn(|| {
         let layout = Layout::new::<i32>();
         for _ in 0..100 {
-            let ptr = unsafe { ALLOQER.assume_init_mut().alloq(layout) };
-            unsafe { ALLOQER.assume_init_mut().dealloq(ptr, layout) };
+            let ptr = ALLOQER.alloq(layout);
+            unsafe { ALLOQER.dealloq(ptr, layout) };
         }
     });
     for _ in 0..100 {
-        let ptr = unsafe { ALLOQER.assume_init_mut().alloq(layout) };
-        unsafe { ALLOQER.assume_init_mut().dealloq(ptr, layout) };
+        let ptr = ALLOQER.alloq(layout);
+        unsafe { ALLOQER.dealloq(ptr, layout) };
     }
     thread.join().unwrap();
 }
@@ -114,7 +115,7 @@ fn custom_structs() {
         _bar: [u16; 8],
         _baz: &'static str,
     }
-    let mut heap_stackish = [0u8; crate::get_size_hint_in::<S, Alloq>(10) * 2];
+    let mut heap_stackish = [0u8; 1024 * 2];
     let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
     let mut v = Vec::with_capacity_in(10, &alloqer);
     for x in 0..10 {
@@ -140,7 +141,7 @@ fn custom_structs() {
 #[test]
 fn full_heap() {
     const VECTOR_SIZE: usize = 16;
-    let mut heap_stackish = [0u8; crate::get_size_hint_in::<[u16; 32], Alloq>(VECTOR_SIZE) * 2];
+    let mut heap_stackish = [0u8; 1024 * 4];
     let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
     let mut v = Vec::with_capacity_in(VECTOR_SIZE, &alloqer);
     for x in 0..VECTOR_SIZE {
@@ -151,15 +152,136 @@ fn full_heap() {
 
 #[test]
 fn zero_sized() {
-    const VECTOR_SIZE: usize = 1024;
-    // FIXME: 32768 bytes for ZST? Really?
-    let mut heap_stackish = [0u8; crate::get_size_hint_in::<(), Alloq>(VECTOR_SIZE)];
+    const ZSTS: usize = 1 << 22;
+    let mut heap_stackish = [0u8; 1024 * 4];
     let alloqer = Alloq::new(heap_stackish.as_mut_ptr_range());
-    let mut v = Vec::with_capacity_in(VECTOR_SIZE, &alloqer);
-    for _ in 0..VECTOR_SIZE {
-        v.push(());
+    for align in [1, 8, 4096] {
+        let layout = Layout::from_size_align(0, align).unwrap();
+        let block = alloqer.allocate(layout).unwrap();
+        assert_eq!(block.len(), 0);
+        assert!((block.as_ptr() as *mut u8 as usize).is_multiple_of(align));
+    }
+    // ZSTs never touch the heap, so they can't exhaust it, even without deallocating.
+    let layout = Layout::new::<()>();
+    for _ in 0..ZSTS {
+        assert!(!alloqer.alloq(layout).is_null());
+    }
+    let real = Layout::new::<[u64; 8]>();
+    let ptr = alloqer.alloq(real);
+    assert!(!ptr.is_null());
+    unsafe {
+        let zst = alloqer.allocate(layout).unwrap().cast::<u8>();
+        let block = alloqer.grow(zst, layout, real).unwrap();
+        assert!(block.len() >= real.size());
+        let zst = alloqer.shrink(block.cast(), real, layout).unwrap();
+        assert_eq!(zst.len(), 0);
+        alloqer.dealloq(zst.as_ptr().cast(), layout);
+        alloqer.dealloq(ptr, real);
+    }
+    let mut v = Vec::with_capacity_in(ZSTS, &alloqer);
+    v.extend((0..ZSTS).map(|_| ()));
+    assert_eq!(v.len(), ZSTS);
+}
+
+#[test]
+fn over_aligned() {
+    // Bigger than any metadata, and than `pool`'s chunks.
+    const HUGE: usize = 2 * 1024 * 1024;
+    let mut heap = alloc::vec![0u8; HUGE * 8];
+    let alloqer = Alloq::new(heap.as_mut_ptr_range());
+    for align in [64, 4096, HUGE] {
+        for size in [8, align, align + 8] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptrs: Vec<_> = (0..2u8)
+                .map(|i| {
+                    let ptr = alloqer.alloq(layout);
+                    assert!(!ptr.is_null(), "can't allocate {layout:?}");
+                    assert!((ptr as usize).is_multiple_of(align));
+                    unsafe { ptr.write_bytes(i, size) };
+                    ptr
+                })
+                .collect();
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                let block = unsafe { core::slice::from_raw_parts(ptr, size) };
+                assert!(block.iter().all(|&b| b == i as u8), "overlapped allocation");
+            }
+            for ptr in ptrs {
+                unsafe { alloqer.dealloq(ptr, layout) };
+            }
+            // `statiq` never frees.
+            unsafe { alloqer.reset() };
+        }
+    }
+}
+
+#[test]
+fn usable_size() {
+    let mut heap = alloc::vec![0u8; 1 << 16];
+    let alloqer = Alloq::new(heap.as_mut_ptr_range());
+    let layouts = [
+        Layout::new::<u8>(),
+        Layout::new::<[u8; 13]>(),
+        Layout::new::<[u64; 8]>(),
+        Layout::new::<[u32; 25]>(),
+        Layout::from_size_align(24, 32).unwrap(),
+        Layout::from_size_align(8, 512).unwrap(),
+    ];
+    let blocks: Vec<_> = layouts
+        .iter()
+        .enumerate()
+        .map(|(i, &layout)| {
+            let block = alloqer.allocate(layout).expect("can't allocate");
+            assert!(block.len() >= layout.size());
+            let ptr = block.cast::<u8>();
+            assert_eq!(unsafe { alloqer.usable_size(ptr, layout) }, block.len());
+            unsafe { ptr.write_bytes(i as u8, block.len()) };
+            (block, layout)
+        })
+        .collect();
+    for (i, (block, layout)) in blocks.into_iter().enumerate() {
+        let bytes = unsafe { block.as_ref() };
+        assert!(bytes.iter().all(|&b| b == i as u8), "overlapped slack");
+        // The whole block fits the layout it's freed with.
+        let fitting = Layout::from_size_align(block.len(), layout.align()).unwrap();
+        unsafe { alloqer.deallocate(block.cast(), fitting) };
+    }
+}
+
+/// Allocates `layouts` in a heap of exactly `size` bytes, starting 4 KiB aligned.
+fn fits(layouts: &[Layout], size: usize) -> bool {
+    const ALIGN: usize = 4096;
+    let mut buffer = alloc::vec![0u8; size + ALIGN];
+    let start = buffer
+        .as_mut_ptr()
+        .map_addr(|addr| crate::align_up(addr, ALIGN));
+    let alloqer = Alloq::new(start..start.wrapping_add(size));
+    layouts
+        .iter()
+        .all(|&layout| !alloqer.alloq(layout).is_null())
+}
+
+#[test]
+fn required_heap_size() {
+    let layouts = [
+        Layout::new::<u8>(),
+        Layout::new::<u64>(),
+        Layout::new::<[u16; 5]>(),
+        Layout::new::<()>(),
+        Layout::from_size_align(24, 32).unwrap(),
+        Layout::new::<[u64; 20]>(),
+        Layout::from_size_align(8, 512).unwrap(),
+        Layout::new::<u32>(),
+        Layout::new::<[u8; 3]>(),
+    ];
+    let size = Alloq::required_heap_size(&layouts);
+    assert_eq!(size.is_some(), PLANS_HEAP, "planned {size:?}");
+    if let Some(size) = size {
+        assert!(fits(&layouts, size), "{size} bytes can't fit them");
+        assert!(
+            size == 0 || !fits(&layouts, size - 1),
+            "{size} bytes isn't the smallest heap"
+        );
     }
-    assert_eq!(v.len(), VECTOR_SIZE);
 }
 
 #[test]
@@ -209,3 +331,68 @@ fn corrupted_heap() {
     }
     assert_eq!(v.iter().sum::<i32>(), 45i32);
 }
+
+#[test]
+fn out_of_memory() {
+    use core::alloc::GlobalAlloc;
+
+    let mut heap = [0u8; 1024];
+    let alloqer = Alloq::new(heap.as_mut_ptr_range());
+    let huge = Layout::from_size_align(isize::MAX as usize / 2, 8).unwrap();
+    // `black_box` keeps the optimizer from assuming `crate::system`'s `malloc` succeeds.
+    assert!(core::hint::black_box(alloqer.allocate(huge)).is_err());
+    assert!(core::hint::black_box(alloqer.alloq(huge)).is_null());
+    assert!(core::hint::black_box(unsafe { crate::AsGlobal::from_ref(&alloqer).alloc(huge) })
+        .is_null());
+    unsafe { alloqer.dealloq(null_mut(), huge) };
+    // A failed allocation doesn't break the allocator.
+    let b = Box::new_in(42u64, &alloqer);
+    assert_eq!(*b, 42);
+}
+
+#[test]
+fn exhausted_heap() {
+    const BLOCKS: usize = 256;
+    let mut heap = [0u8; 1024 * 4];
+    let alloqer = Alloq::new(heap.as_mut_ptr_range());
+    let layout = Layout::new::<[u8; 64]>();
+    let mut ptrs = Vec::new();
+    // Heap-less allocators (like `crate::system`) never run out, so bound it.
+    for _ in 0..BLOCKS {
+        let ptr = alloqer.alloq(layout);
+        if ptr.is_null() {
+            break;
+        }
+        ptrs.push(ptr);
+    }
+    assert!(!ptrs.is_empty());
+    if ptrs.len() < BLOCKS {
+        assert!(alloqer.alloq(layout).is_null(), "still exhausted");
+    }
+    for ptr in ptrs.drain(..).rev() {
+        unsafe { alloqer.dealloq(ptr, layout) };
+    }
+    unsafe { alloqer.reset() };
+    assert!(!alloqer.alloq(layout).is_null());
+}
+
+/// Random operations checked by `crate::model`, from the seeds 0 to 15 or from `ALLOQ_SEED`
+/// alone, to reproduce a failure.
+#[test]
+fn model() {
+    let seeds = match std::env::var("ALLOQ_SEED") {
+        Ok(seed) => {
+            let seed = seed.parse().expect("ALLOQ_SEED isn't a number");
+            seed..seed + 1
+        }
+        Err(_) => 0..16,
+    };
+    for seed in seeds {
+        let mut heap = alloc::vec![0u8; 1 << 18];
+        let alloqer = Alloq::new(heap.as_mut_ptr_range());
+        let bytes = crate::model::seeded_bytes(seed, 1 << 12);
+        let summary = crate::model::check(&alloqer, crate::model::Ops::new(&bytes))
+            .unwrap_or_else(|violation| panic!("seed {seed}: {violation}"));
+        assert!(summary.succeeded > 0, "seed {seed}: nothing was allocated");
+    }
+}
@@ -0,0 +1,393 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    fmt,
+    ops::Range,
+    ptr::NonNull,
+    str::FromStr,
+};
+
+use crate::{
+    lock::{Mutex, RawLock, Spin},
+    Affixes, Alloqator,
+};
+
+/// An operation of a trace. Pointers are just addresses, to match a `Free` or a `Realloc` with
+/// the `Alloc` that returned it; a failed allocation has a null one. Times come from
+/// `Trace::clock`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Alloc {
+        time: u64,
+        ptr: usize,
+        size: usize,
+        align: usize,
+    },
+    Free {
+        time: u64,
+        ptr: usize,
+        size: usize,
+        align: usize,
+    },
+    /// A `grow` or a `shrink`, from `ptr` to `new_ptr`.
+    Realloc {
+        time: u64,
+        ptr: usize,
+        size: usize,
+        align: usize,
+        new_ptr: usize,
+        new_size: usize,
+        new_align: usize,
+    },
+    Reset {
+        time: u64,
+    },
+}
+
+impl Event {
+    pub const fn time(&self) -> u64 {
+        match *self {
+            Self::Alloc { time, .. }
+            | Self::Free { time, .. }
+            | Self::Realloc { time, .. }
+            | Self::Reset { time } => time,
+        }
+    }
+}
+
+/// A line of text per event: `a time ptr size align`, `f time ptr size align`,
+/// `r time ptr size align new_ptr new_size new_align` or `x time`.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Alloc {
+                time,
+                ptr,
+                size,
+                align,
+            } => write!(f, "a {time} {ptr:x} {size} {align}"),
+            Self::Free {
+                time,
+                ptr,
+                size,
+                align,
+            } => write!(f, "f {time} {ptr:x} {size} {align}"),
+            Self::Realloc {
+                time,
+                ptr,
+                size,
+                align,
+                new_ptr,
+                new_size,
+                new_align,
+            } => write!(
+                f,
+                "r {time} {ptr:x} {size} {align} {new_ptr:x} {new_size} {new_align}"
+            ),
+            Self::Reset { time } => write!(f, "x {time}"),
+        }
+    }
+}
+
+/// A line that isn't an `Event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseEventError;
+
+impl FromStr for Event {
+    type Err = ParseEventError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = [""; 8];
+        let mut len = 0;
+        for field in line.split_whitespace() {
+            *fields.get_mut(len).ok_or(ParseEventError)? = field;
+            len += 1;
+        }
+        let hex = |i: usize| usize::from_str_radix(fields[i], 16).map_err(|_| ParseEventError);
+        let dec = |i: usize| fields[i].parse().map_err(|_| ParseEventError);
+        let time = fields[1].parse().map_err(|_| ParseEventError)?;
+        Ok(match (fields[0], len) {
+            ("a", 5) => Self::Alloc {
+                time,
+                ptr: hex(2)?,
+                size: dec(3)?,
+                align: dec(4)?,
+            },
+            ("f", 5) => Self::Free {
+                time,
+                ptr: hex(2)?,
+                size: dec(3)?,
+                align: dec(4)?,
+            },
+            ("r", 8) => Self::Realloc {
+                time,
+                ptr: hex(2)?,
+                size: dec(3)?,
+                align: dec(4)?,
+                new_ptr: hex(5)?,
+                new_size: dec(6)?,
+                new_align: dec(7)?,
+            },
+            ("x", 2) => Self::Reset { time },
+            _ => return Err(ParseEventError),
+        })
+    }
+}
+
+/// Writes each event as a line of text, e.g. to a serial port, ignoring write errors. Put it in
+/// a `static` to record to it: `Trace::with(alloqer, |event| SINK.record(event))`.
+pub struct TextSink<W: fmt::Write, L: RawLock = Spin>(pub Mutex<L, W>);
+
+impl<W: fmt::Write, L: RawLock> TextSink<W, L> {
+    pub const fn new(writer: W) -> Self {
+        Self(Mutex::new(writer))
+    }
+
+    pub fn record(&self, event: Event) {
+        let _ = writeln!(self.0.lock(), "{event}");
+    }
+}
+
+/// Calls `on_event` with every operation on `inner`. Frees are recorded before they happen and
+/// the others after, so that an address reused by another thread is always freed first in the
+/// trace. `on_event` is called from the allocator, so it can't allocate from it.
+pub struct Trace<A: Alloqator> {
+    pub inner: A,
+    pub on_event: fn(Event),
+    pub clock: fn() -> u64,
+}
+
+impl<A: Alloqator> Trace<A> {
+    /// Without a clock, every time is 0.
+    pub const fn with(inner: A, on_event: fn(Event)) -> Self {
+        Self {
+            inner,
+            on_event,
+            clock: || 0,
+        }
+    }
+
+    #[inline(always)]
+    pub const fn clock(mut self, clock: fn() -> u64) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    #[inline(always)]
+    fn alloc(
+        &self,
+        layout: Layout,
+        block: Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (self.on_event)(Event::Alloc {
+            time: (self.clock)(),
+            ptr: block.map_or(0, |block| block.cast::<u8>().as_ptr() as usize),
+            size: layout.size(),
+            align: layout.align(),
+        });
+        block
+    }
+
+    #[inline(always)]
+    fn realloc(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        block: Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (self.on_event)(Event::Realloc {
+            time: (self.clock)(),
+            ptr: ptr.as_ptr() as usize,
+            size: old_layout.size(),
+            align: old_layout.align(),
+            new_ptr: block.map_or(0, |block| block.cast::<u8>().as_ptr() as usize),
+            new_size: new_layout.size(),
+            new_align: new_layout.align(),
+        });
+        block
+    }
+}
+
+unsafe impl<A: Alloqator> Allocator for Trace<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc(layout, self.inner.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc(layout, self.inner.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (self.on_event)(Event::Free {
+            time: (self.clock)(),
+            ptr: ptr.as_ptr() as usize,
+            size: layout.size(),
+            align: layout.align(),
+        });
+        self.inner.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.inner.grow(ptr, old_layout, new_layout);
+        self.realloc(ptr, old_layout, new_layout, block)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.inner.grow_zeroed(ptr, old_layout, new_layout);
+        self.realloc(ptr, old_layout, new_layout, block)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.inner.shrink(ptr, old_layout, new_layout);
+        self.realloc(ptr, old_layout, new_layout, block)
+    }
+}
+
+impl<A: Alloqator> Alloqator for Trace<A> {
+    type Metadata = A::Metadata;
+
+    fn required_heap_size(layouts: &[Layout]) -> Option<usize> {
+        A::required_heap_size(layouts)
+    }
+
+    fn required_affixed_heap_size(layouts: &[Layout], affixes: Affixes) -> Option<usize> {
+        A::required_affixed_heap_size(layouts, affixes)
+    }
+
+    /// Without a callback, it records nothing.
+    fn new(heap_range: Range<*mut u8>) -> Self {
+        Self::with(A::new(heap_range), |_| {})
+    }
+
+    fn heap_start(&self) -> *mut u8 {
+        self.inner.heap_start()
+    }
+
+    fn heap_end(&self) -> *mut u8 {
+        self.inner.heap_end()
+    }
+
+    unsafe fn reset(&self) {
+        self.inner.reset();
+        (self.on_event)(Event::Reset {
+            time: (self.clock)(),
+        });
+    }
+
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        self.inner.usable_size(ptr, layout)
+    }
+}
+
+crate::impl_allocator!(Trace<A: Alloqator>);
+
+#[cfg(all(test, feature = "list"))]
+pub mod tests {
+    type Alloq = super::Trace<crate::list::first::Alloq>;
+
+    const PLANS_HEAP: bool = true;
+
+    include!("test.template.rs");
+
+    use super::{Event, ParseEventError, TextSink, Trace};
+
+    static EVENTS: std::sync::Mutex<Vec<Event>> = std::sync::Mutex::new(Vec::new());
+
+    #[test]
+    fn records() {
+        let mut heap = [0u8; 1024 * 4];
+        let alloqer = Trace::with(
+            crate::list::first::Alloq::new(heap.as_mut_ptr_range()),
+            |event| EVENTS.lock().unwrap().push(event),
+        )
+        .clock(|| 7);
+        let mut v = Vec::with_capacity_in(2, &alloqer);
+        v.extend(0..4u64);
+        drop(v);
+        unsafe { alloqer.reset() };
+        let events = EVENTS.lock().unwrap();
+        let (Event::Alloc { ptr, .. }, Event::Realloc { new_ptr, .. }) = (events[0], events[1])
+        else {
+            panic!("unexpected trace: {events:?}");
+        };
+        let expected = [
+            Event::Alloc {
+                time: 7,
+                ptr,
+                size: 16,
+                align: 8,
+            },
+            Event::Realloc {
+                time: 7,
+                ptr,
+                size: 16,
+                align: 8,
+                new_ptr,
+                new_size: 32,
+                new_align: 8,
+            },
+            Event::Free {
+                time: 7,
+                ptr: new_ptr,
+                size: 32,
+                align: 8,
+            },
+            Event::Reset { time: 7 },
+        ];
+        assert_eq!(events[..], expected);
+    }
+
+    #[test]
+    fn text() {
+        let events = [
+            Event::Alloc {
+                time: 1,
+                ptr: 0x7f00_1000,
+                size: 24,
+                align: 8,
+            },
+            Event::Realloc {
+                time: 2,
+                ptr: 0x7f00_1000,
+                size: 24,
+                align: 8,
+                new_ptr: 0,
+                new_size: 4096,
+                new_align: 4096,
+            },
+            Event::Free {
+                time: 3,
+                ptr: 0x7f00_1000,
+                size: 24,
+                align: 8,
+            },
+            Event::Reset { time: 4 },
+        ];
+        let sink = TextSink::<alloc::string::String>::new(alloc::string::String::new());
+        for event in events {
+            sink.record(event);
+        }
+        let text = sink.0.into_inner();
+        assert_eq!(text.lines().next(), Some("a 1 7f001000 24 8"));
+        let parsed: Result<Vec<Event>, _> = text.lines().map(str::parse).collect();
+        assert_eq!(parsed.unwrap(), events);
+        assert_eq!("a 1 7f001000 24".parse::<Event>(), Err(ParseEventError));
+        assert_eq!("x 1 2".parse::<Event>(), Err(ParseEventError));
+        assert_eq!("q 1".parse::<Event>(), Err(ParseEventError));
+    }
+}
@@ -0,0 +1,67 @@
+#[path = "../benches/replay/resolve.rs"]
+mod resolve;
+
+use std::alloc::Layout;
+
+use alloq::trace::Event;
+use resolve::{resolve, Op};
+
+fn alloc(ptr: usize, size: usize) -> Event {
+    Event::Alloc {
+        time: 0,
+        ptr,
+        size,
+        align: 8,
+    }
+}
+
+fn free(ptr: usize, size: usize) -> Event {
+    Event::Free {
+        time: 0,
+        ptr,
+        size,
+        align: 8,
+    }
+}
+
+fn realloc(ptr: usize, size: usize, new_ptr: usize, new_size: usize) -> Event {
+    Event::Realloc {
+        time: 0,
+        ptr,
+        size,
+        align: 8,
+        new_ptr,
+        new_size,
+        new_align: 8,
+    }
+}
+
+/// Zero-sized blocks all live at the dangling address 8, so they're skipped instead of taking
+/// each other's ids.
+#[test]
+fn zero_sized_blocks() {
+    let layout = |size| Layout::from_size_align(size, 8).unwrap();
+    let events = [
+        alloc(0x1000, 16),
+        alloc(8, 0),
+        alloc(8, 0),
+        free(8, 0),
+        realloc(8, 0, 0x2000, 32),
+        realloc(0x1000, 16, 0x3000, 64),
+        free(0x3000, 64),
+        realloc(0x2000, 32, 8, 0),
+        free(8, 0),
+    ];
+    let (ops, blocks) = resolve(&events);
+    assert_eq!(
+        ops,
+        [
+            Op::Alloc(0, layout(16)),
+            Op::Alloc(1, layout(32)),
+            Op::Realloc(0, layout(64)),
+            Op::Free(0),
+            Op::Free(1),
+        ]
+    );
+    assert_eq!(blocks, 2);
+}